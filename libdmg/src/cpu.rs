@@ -1,22 +1,81 @@
+use std::cell::Cell;
+
 use super::peripherals::{InterruptMask, InterruptSource, Peripherals};
+use super::state::{Snapshot, StateError, StateReader, StateWriter};
 
 mod decoder;
+mod disassembler;
 mod pc_reader;
 mod registers;
+mod trace;
 
 use decoder::{
     AddToSpDestination, ArithmeticLogic, BitOp, Condition, Destination, IncDecDirection,
-    Instruction, LoadMemoryDirection, LoadMemoryLocation, Operand8, ResetSlot, RotateCarry,
-    RotateDirection,
+    Instruction, LoadMemoryDirection, LoadMemoryLocation, Operand16, Operand8, ResetSlot,
+    RotateCarry, RotateDirection,
 };
+pub(crate) use disassembler::disassemble_with;
+pub use disassembler::{disassemble, disassemble_linear, DisassembledInstruction, LinearSweep};
 use pc_reader::PcReader;
 use registers::Registers;
+pub use trace::TraceFilter;
+pub(crate) use trace::Tracer;
+
+/// A copy of the CPU registers and execution state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuState {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable
+    pub ime: bool,
+    pub halted: bool,
+    /// The CPU executed STOP and waits for joypad input
+    pub stopped: bool,
+    /// The CPU executed an illegal opcode and froze until the next reset
+    pub locked_up: bool,
+    /// The number of cycles since power on. Read-only, it is ignored by `Dmg::set_cpu_state`.
+    pub cycle: u64,
+}
 
+impl CpuState {
+    fn flag(&self, bit: u8) -> bool {
+        (self.af & (1 << bit)) != 0
+    }
+
+    pub fn zero(&self) -> bool {
+        self.flag(7)
+    }
+
+    pub fn subtract(&self) -> bool {
+        self.flag(6)
+    }
+
+    pub fn half_carry(&self) -> bool {
+        self.flag(5)
+    }
+
+    pub fn carry(&self) -> bool {
+        self.flag(4)
+    }
+}
+
+#[derive(Clone)]
 pub struct Cpu {
     cycle: u64,
     registers: Registers,
     halted: bool,
+    stopped: bool,
+    locked_up: bool,
     interrupt_enable: bool,
+    /// EI was executed, IME is set after the next instruction
+    interrupt_enable_delayed: bool,
+    /// HALT was executed with IME=0 and an interrupt pending,
+    /// so PC is not incremented after fetching the next opcode
+    halt_bug: bool,
 }
 
 impl Cpu {
@@ -25,7 +84,11 @@ impl Cpu {
             cycle: 0,
             registers: Registers::new(),
             halted: false,
+            stopped: false,
+            locked_up: false,
             interrupt_enable: false,
+            interrupt_enable_delayed: false,
+            halt_bug: false,
         }
     }
 
@@ -33,47 +96,187 @@ impl Cpu {
         self.cycle
     }
 
-    pub(crate) fn run(&mut self, peripherals: &mut Peripherals, cycles: u64) {
-        let end_cycle = self.cycle + cycles;
+    pub(crate) fn state(&self) -> CpuState {
+        CpuState {
+            af: self.registers.read_double(Operand16::Af),
+            bc: self.registers.bc(),
+            de: self.registers.de(),
+            hl: self.registers.hl(),
+            sp: self.registers.sp,
+            pc: self.registers.pc,
+            ime: self.interrupt_enable,
+            halted: self.halted,
+            stopped: self.stopped,
+            locked_up: self.locked_up,
+            cycle: self.cycle,
+        }
+    }
 
-        while self.cycle < end_cycle {
-            self.step(peripherals)
+    pub(crate) fn set_state(&mut self, state: &CpuState, peripherals: &mut Peripherals) {
+        // The lower four bits of F do not exist and always read as zero
+        self.registers
+            .write_double(Operand16::Af, state.af & 0xfff0);
+        self.registers.write_double(Operand16::Bc, state.bc);
+        self.registers.write_double(Operand16::De, state.de);
+        self.registers.write_double(Operand16::Hl, state.hl);
+        self.registers.sp = state.sp;
+        self.registers.pc = state.pc;
+        self.interrupt_enable = state.ime;
+        self.halted = state.halted;
+        self.locked_up = state.locked_up;
+
+        // Neither a pending EI nor the HALT bug belong to the new state
+        self.interrupt_enable_delayed = false;
+        self.halt_bug = false;
+
+        if state.stopped != self.stopped {
+            self.stopped = state.stopped;
+
+            if self.stopped {
+                peripherals.stop(self.cycle);
+            } else {
+                peripherals.resume(self.cycle);
+            }
         }
     }
 
-    pub(crate) fn step(&mut self, peripherals: &mut Peripherals) {
-        let mut pc = self.registers.pc;
+    pub(crate) fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    pub(crate) fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub(crate) fn stopped(&self) -> bool {
+        self.stopped
+    }
 
-        if self.interrupt_enable {
-            let mut reg_if: InterruptMask = peripherals.read(self.cycle, 0xff0f).into();
-            let reg_ie: InterruptMask = peripherals.read(self.cycle, 0xffff).into();
+    /// The CPU is in STOP mode and there is no joypad input that wakes it up
+    pub(crate) fn stopped_waiting(&self, peripherals: &Peripherals) -> bool {
+        self.stopped && !peripherals.joypad_input()
+    }
 
-            let pending = reg_if & reg_ie;
+    pub(crate) fn locked_up(&self) -> bool {
+        self.locked_up
+    }
 
-            if let Some(interrupt) = pending.highest_priority() {
-                let sp = self.registers.sp.wrapping_sub(2);
-                peripherals.write_u16(self.cycle, sp, pc);
-                self.registers.sp = sp;
+    /// An enabled interrupt is pending, independent of IME
+    fn interrupt_pending(&self, peripherals: &Peripherals) -> bool {
+        let reg_if: InterruptMask = peripherals.peek(self.cycle, 0xff0f).into();
+        let reg_ie: InterruptMask = peripherals.peek(self.cycle, 0xffff).into();
 
-                reg_if.clear(interrupt);
-                peripherals.write(self.cycle, 0xff0f, reg_if.into());
-                self.halted = false;
-                self.interrupt_enable = false;
-                pc = interrupt.vector_address();
+        (reg_if & reg_ie).highest_priority().is_some()
+    }
+
+    /// The CPU is halted and there is no interrupt that could wake it up.
+    /// An enabled interrupt also ends HALT if IME is not set, it is just not dispatched.
+    pub(crate) fn halted_forever(&self, peripherals: &Peripherals) -> bool {
+        if !self.halted {
+            return false;
+        }
+
+        let reg_ie: InterruptMask = peripherals.peek(self.cycle, 0xffff).into();
+
+        let pending = self.interrupt_pending(peripherals);
+        let upcoming = peripherals.next_pending_masked(self.cycle, reg_ie) != u64::MAX;
+
+        !(pending || upcoming)
+    }
+
+    /// Execute a single instruction or dispatch an interrupt.
+    /// A halted CPU idles until the next interrupt becomes pending,
+    /// but at most until `halt_limit` (and at least for one M-cycle).
+    /// Executed instructions are written to `tracer` if given.
+    pub(crate) fn step(
+        &mut self,
+        peripherals: &mut Peripherals,
+        halt_limit: u64,
+        tracer: Option<&mut Tracer>,
+    ) {
+        let mut pc = self.registers.pc;
+
+        if self.locked_up {
+            // Neither instructions nor interrupts are executed anymore, only time passes
+            self.cycle = halt_limit.max(self.cycle + 4);
+            return;
+        }
+
+        if self.stopped {
+            if !peripherals.joypad_input() {
+                self.cycle = halt_limit.max(self.cycle + 4);
+                return;
             }
+
+            self.stopped = false;
+            peripherals.resume(self.cycle);
         }
 
-        if self.halted {
-            self.cycle = peripherals.next_pending(self.cycle);
+        if self.interrupt_enable && self.interrupt_pending(peripherals) {
+            self.dispatch_interrupt(peripherals);
             return;
         }
 
+        if self.halted {
+            if !self.interrupt_pending(peripherals) {
+                let reg_ie: InterruptMask = peripherals.peek(self.cycle, 0xffff).into();
+                let wakeup = peripherals
+                    .next_pending_masked(self.cycle, reg_ie)
+                    .min(halt_limit);
+                self.cycle = wakeup.max(self.cycle + 4);
+                return;
+            }
+
+            // With IME=0 the CPU wakes up and continues without dispatching the interrupt
+            self.halted = false;
+        }
+
+        if let Some(tracer) = tracer {
+            tracer.trace(self.cycle, &self.registers, peripherals);
+        }
+
+        // EI takes effect after the instruction following it,
+        // so that instruction is not interrupted yet but can undo it using DI.
+        if self.interrupt_enable_delayed {
+            self.interrupt_enable_delayed = false;
+            self.interrupt_enable = true;
+        }
+
         let inst = {
-            let mut reader = PcReader::new(self.cycle, &mut pc, peripherals);
-            Instruction::from_pc_reader(&mut reader)
+            // Instruction fetches are covered by execute breakpoints, not read watchpoints.
+            // Every byte of the instruction is fetched in its own M-cycle.
+            let cycle = self.cycle;
+            let fetches = Cell::new(0);
+            let halt_bug = std::mem::take(&mut self.halt_bug);
+
+            // The HALT bug reads the opcode byte twice. Decoding starts one byte early
+            // with the opcode mapped there, so PC ends up one byte short.
+            let opcode_addr = pc;
+            if halt_bug {
+                pc = pc.wrapping_sub(1);
+            }
+
+            let memory = |addr: u16| {
+                let addr = if halt_bug && addr == opcode_addr.wrapping_sub(1) {
+                    opcode_addr
+                } else {
+                    addr
+                };
+
+                let fetch_cycle = cycle + fetches.get() * 4;
+                fetches.set(fetches.get() + 1);
+
+                peripherals.peek(fetch_cycle, addr)
+            };
+            let mut reader = PcReader::new(&mut pc, &memory);
+            let inst = Instruction::from_pc_reader(&mut reader);
+
+            self.cycle += fetches.get() * 4;
+
+            inst
         };
 
-        let cycles = match inst {
+        match inst {
             Instruction::Add16(operand) => {
                 let hl = self.registers.hl();
                 let other = self.registers.read_double(operand);
@@ -91,7 +294,7 @@ impl Cpu {
                 self.registers.set_bcd_n(false);
                 self.registers.set_bcd_h(half_carry);
 
-                8
+                self.tick();
             }
             Instruction::AddToSp(destination, immediate) => {
                 let (res, carry) = self.registers.sp.overflowing_add_signed(immediate as i16);
@@ -103,16 +306,21 @@ impl Cpu {
                     .set_bcd_h((res ^ self.registers.sp) & 0b0001_0000 != 0);
 
                 match destination {
-                    AddToSpDestination::Hl => self.registers.set_hl(res),
-                    AddToSpDestination::Sp => self.registers.sp = res,
+                    AddToSpDestination::Hl => {
+                        self.registers.set_hl(res);
+                        self.tick();
+                    }
+                    AddToSpDestination::Sp => {
+                        self.registers.sp = res;
+                        self.tick();
+                        self.tick();
+                    }
                 }
-
-                16
             }
             Instruction::ArithmeticLogic8(operation, operand) => {
                 let c = self.registers.carry();
                 let a = self.registers.a;
-                let (load_cycles, other) = self.load_operand8(peripherals, operand);
+                let other = self.load_operand8(peripherals, operand);
 
                 let half_a = a & 0x0f;
                 let half_other = other & 0x0f;
@@ -161,24 +369,22 @@ impl Cpu {
                 if !matches!(operation, ArithmeticLogic::Cp) {
                     self.registers.a = val;
                 }
-
-                load_cycles + 4
             }
             Instruction::BitOp(operation, operand) => {
-                let (load_cycles, input) = self.load_operand8(peripherals, operand);
+                let input = self.load_operand8(peripherals, operand);
 
                 let (flag_z, flag_n, flag_h, flag_c, val) = match operation {
                     BitOp::Rotate(RotateDirection::Left, RotateCarry::Through) => {
                         // RLC
                         let c = (input & 0b1000_0000) != 0;
-                        let val = (input << 1) | (input >> 7);
+                        let val = input.rotate_left(1);
                         let z = val == 0;
                         (Some(z), Some(false), Some(false), Some(c), Some(val))
                     }
                     BitOp::Rotate(RotateDirection::Right, RotateCarry::Through) => {
                         // RRC
                         let c = (input & 0b0000_0001) != 0;
-                        let val = (input >> 1) | (input << 7);
+                        let val = input.rotate_right(1);
                         let z = val == 0;
                         (Some(z), Some(false), Some(false), Some(c), Some(val))
                     }
@@ -209,7 +415,7 @@ impl Cpu {
                         (Some(z), Some(false), Some(false), Some(c), Some(val))
                     }
                     BitOp::SwapNibbles => {
-                        let val = (input >> 4) | (input << 4);
+                        let val = input.rotate_left(4);
                         let z = val == 0;
                         (Some(z), Some(false), Some(false), Some(false), Some(val))
                     }
@@ -233,10 +439,9 @@ impl Cpu {
                     }
                 };
 
-                let store_cycles = match val {
-                    Some(val) => self.store_operand8(peripherals, operand, val),
-                    None => 0,
-                };
+                if let Some(val) = val {
+                    self.store_operand8(peripherals, operand, val);
+                }
 
                 if let Some(z) = flag_z {
                     self.registers.set_zero(z);
@@ -253,36 +458,27 @@ impl Cpu {
                 if let Some(c) = flag_c {
                     self.registers.set_carry(c);
                 }
-
-                load_cycles + store_cycles + 2
             }
             Instruction::Call(destination, condition) => {
-                let (load_cycles, addr) = self.jump_destination(pc, destination);
+                let addr = self.jump_destination(pc, destination);
 
                 if self.check_condition(condition) {
-                    let sp = self.registers.sp.wrapping_sub(2);
-                    peripherals.write_u16(self.cycle, sp, pc);
-                    self.registers.sp = sp;
+                    self.tick();
+                    self.push(peripherals, pc);
 
                     pc = addr;
-                    load_cycles + 4
-                } else {
-                    load_cycles
                 }
             }
             Instruction::Ccf => {
                 self.registers.set_carry(false);
-                4
             }
             Instruction::Cpl => {
                 self.registers.a ^= 0xff;
                 self.registers.set_bcd_n(true);
                 self.registers.set_bcd_h(true);
-                4
             }
             Instruction::Ei => {
-                self.interrupt_enable = true;
-                4
+                self.interrupt_enable_delayed = true;
             }
             Instruction::Daa => {
                 let mut a = self.registers.a;
@@ -307,33 +503,32 @@ impl Cpu {
                 self.registers.set_zero(a == 0);
                 self.registers.set_bcd_h(false);
                 self.registers.a = a;
-
-                4
             }
             Instruction::Di => {
                 self.interrupt_enable = false;
-                4
             }
             Instruction::Halt => {
-                self.halted = true;
-                4
+                if !self.interrupt_enable && self.interrupt_pending(peripherals) {
+                    // HALT ends immediately, but the next opcode is read twice
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
             }
             Instruction::IncDec8(direction, operand) => {
-                let (load_cycles, value) = self.load_operand8(peripherals, operand);
+                let value = self.load_operand8(peripherals, operand);
 
                 let (result, flag_n) = match direction {
                     IncDecDirection::Dec => (value.wrapping_sub(1), true),
                     IncDecDirection::Inc => (value.wrapping_add(1), false),
                 };
 
-                let store_cycles = self.store_operand8(peripherals, operand, result);
+                self.store_operand8(peripherals, operand, result);
 
                 self.registers.set_zero(result == 0);
                 self.registers.set_bcd_n(flag_n);
                 self.registers
                     .set_bcd_h(((value ^ result) & 0b0001_0000) != 0);
-
-                load_cycles + store_cycles + 4
             }
             Instruction::IncDec16(direction, operand) => {
                 let value = self.registers.read_double(operand);
@@ -344,45 +539,44 @@ impl Cpu {
                 };
 
                 self.registers.write_double(operand, result);
-
-                8
+                self.tick();
             }
             Instruction::Jump(destination, condition) => {
-                let (load_cycles, addr) = self.jump_destination(pc, destination);
+                // Loading PC from HL does not need an extra cycle
+                let from_hl = matches!(destination, Destination::Hl);
+                let addr = self.jump_destination(pc, destination);
 
                 if self.check_condition(condition) {
+                    if !from_hl {
+                        self.tick();
+                    }
+
                     pc = addr;
-                    load_cycles + 4
-                } else {
-                    load_cycles
                 }
             }
             Instruction::LoadHlToSp => {
                 self.registers.sp = self.registers.hl();
-                8
+                self.tick();
             }
             Instruction::LoadImm16(destination, immediate) => {
                 self.registers.write_double(destination, immediate);
-                12
             }
             Instruction::LoadMemory(direction, location) => {
-                let (addr, cycles) = match location {
-                    LoadMemoryLocation::Bc => (self.registers.bc(), 8),
-                    LoadMemoryLocation::De => (self.registers.de(), 8),
-                    LoadMemoryLocation::HlDec | LoadMemoryLocation::HlInc => {
-                        (self.registers.hl(), 8)
-                    }
-                    LoadMemoryLocation::ZeroPageC => (0xff00 | self.registers.c as u16, 8),
-                    LoadMemoryLocation::ZeroPageImm(immediate) => (0xff00 | immediate as u16, 12),
-                    LoadMemoryLocation::Absolute(addr) => (addr, 16),
+                let addr = match location {
+                    LoadMemoryLocation::Bc => self.registers.bc(),
+                    LoadMemoryLocation::De => self.registers.de(),
+                    LoadMemoryLocation::HlDec | LoadMemoryLocation::HlInc => self.registers.hl(),
+                    LoadMemoryLocation::ZeroPageC => 0xff00 | self.registers.c as u16,
+                    LoadMemoryLocation::ZeroPageImm(immediate) => 0xff00 | immediate as u16,
+                    LoadMemoryLocation::Absolute(addr) => addr,
                 };
 
                 match direction {
                     LoadMemoryDirection::FromMemory => {
-                        self.registers.a = peripherals.read(self.cycle, addr);
+                        self.registers.a = self.read(peripherals, addr);
                     }
                     LoadMemoryDirection::ToMemory => {
-                        peripherals.write(self.cycle, addr, self.registers.a);
+                        self.write(peripherals, addr, self.registers.a);
                     }
                 }
 
@@ -395,38 +589,29 @@ impl Cpu {
                     }
                     _ => {}
                 }
-
-                cycles
             }
             Instruction::LoadSimple(destination, source) => {
-                let (load_cycles, value) = self.load_operand8(peripherals, source);
-                let store_cycles = self.store_operand8(peripherals, destination, value);
-                load_cycles + store_cycles + 4
+                let value = self.load_operand8(peripherals, source);
+                self.store_operand8(peripherals, destination, value);
             }
             Instruction::LoadSpToImm(addr) => {
-                peripherals.write_u16(self.cycle, addr, self.registers.sp);
-                20
+                let [low, high] = self.registers.sp.to_le_bytes();
+                self.write(peripherals, addr, low);
+                self.write(peripherals, addr.wrapping_add(1), high);
             }
-            Instruction::Nop => 4,
+            Instruction::Nop => {}
             Instruction::Pop(operand) => {
-                let val = peripherals.read_u16(self.cycle, self.registers.sp);
+                let val = self.pop(peripherals);
                 self.registers.write_double(operand, val);
-                self.registers.sp = self.registers.sp.wrapping_add(2);
-
-                12
             }
             Instruction::Push(operand) => {
                 let val = self.registers.read_double(operand);
-                let sp = self.registers.sp.wrapping_sub(2);
-                peripherals.write_u16(self.cycle, sp, val);
-                self.registers.sp = sp;
-
-                16
+                self.tick();
+                self.push(peripherals, val);
             }
             Instruction::Reset(vector) => {
-                let sp = self.registers.sp.wrapping_sub(2);
-                peripherals.write_u16(self.cycle, sp, pc);
-                self.registers.sp = sp;
+                self.tick();
+                self.push(peripherals, pc);
 
                 pc = match vector {
                     ResetSlot::Slot0 => 0x0000,
@@ -438,28 +623,21 @@ impl Cpu {
                     ResetSlot::Slot6 => 0x0030,
                     ResetSlot::Slot7 => 0x0038,
                 };
-
-                16
             }
             Instruction::Reti => {
-                pc = peripherals.read_u16(self.cycle, self.registers.sp);
-                self.registers.sp = self.registers.sp.wrapping_add(2);
+                pc = self.pop(peripherals);
+                self.tick();
                 self.interrupt_enable = true;
-                4
             }
             Instruction::Return(condition) => {
-                let condition_penalty = match condition {
-                    Condition::Always => 0,
-                    _ => 4,
-                };
+                // Checking the condition takes an extra cycle
+                if !matches!(condition, Condition::Always) {
+                    self.tick();
+                }
 
                 if self.check_condition(condition) {
-                    pc = peripherals.read_u16(self.cycle, self.registers.sp);
-                    self.registers.sp = self.registers.sp.wrapping_add(2);
-
-                    condition_penalty + 16
-                } else {
-                    condition_penalty + 4
+                    pc = self.pop(peripherals);
+                    self.tick();
                 }
             }
             Instruction::RotateA(direction, through) => {
@@ -468,10 +646,10 @@ impl Cpu {
 
                 let (val, carry) = match (direction, through) {
                     (RotateDirection::Left, RotateCarry::Through) => {
-                        (a << 1 | a >> 7, a & 0b1000_0000 != 0)
+                        (a.rotate_left(1), a & 0b1000_0000 != 0)
                     }
                     (RotateDirection::Right, RotateCarry::Through) => {
-                        (a >> 1 | a << 7, a & 0b0000_0001 != 0)
+                        (a.rotate_right(1), a & 0b0000_0001 != 0)
                     }
                     (RotateDirection::Left, RotateCarry::NotThrough) => {
                         (a << 1 | c, a & 0b1000_0000 != 0)
@@ -486,54 +664,131 @@ impl Cpu {
                 self.registers.set_bcd_n(false);
                 self.registers.set_bcd_h(false);
                 self.registers.set_carry(carry);
-
-                4
             }
             Instruction::Scf => {
                 self.registers.set_carry(true);
-                4
             }
             Instruction::Stop => {
-                unimplemented!("STOP")
+                // There is no CGB mode, so there is no KEY1 speed switch to perform.
+                // STOP is two bytes long unless an interrupt is pending,
+                // in that case the byte following it is executed as the next instruction.
+                let pending = self.interrupt_pending(peripherals);
+
+                if !pending {
+                    pc = pc.wrapping_add(1);
+                }
+
+                if !peripherals.joypad_input() {
+                    self.stopped = true;
+                    peripherals.stop(self.cycle);
+                } else if !pending {
+                    // With a button held STOP enters HALT mode instead
+                    self.halted = true;
+                }
+            }
+            Instruction::Invalid(_) => {
+                // The CPU freezes with PC pointing at the illegal opcode
+                self.locked_up = true;
+                pc = self.registers.pc;
             }
-            Instruction::Invalid(op) => {
-                unimplemented!("Invalid instruction {op}")
+        }
+
+        self.registers.pc = pc;
+    }
+
+    /// Dispatch the highest priority pending interrupt, which takes five M-cycles
+    /// (and one more to leave HALT mode).
+    fn dispatch_interrupt(&mut self, peripherals: &mut Peripherals) {
+        if self.halted {
+            self.halted = false;
+            self.tick();
+        }
+
+        self.interrupt_enable = false;
+
+        self.tick();
+        self.tick();
+
+        let [low, high] = self.registers.pc.to_le_bytes();
+
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(peripherals, self.registers.sp, high);
+
+        // The interrupt is only selected after pushing the high byte, which may
+        // have overwritten IE. Without an interrupt left the CPU jumps to 0x0000.
+        let mut reg_if: InterruptMask = peripherals.peek(self.cycle, 0xff0f).into();
+        let reg_ie: InterruptMask = peripherals.peek(self.cycle, 0xffff).into();
+        let interrupt = (reg_if & reg_ie).highest_priority();
+
+        if let Some(interrupt) = interrupt {
+            reg_if.clear(interrupt);
+            peripherals.set_pending(self.cycle, reg_if);
+        }
+
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(peripherals, self.registers.sp, low);
+
+        self.tick();
+
+        self.registers.pc = match interrupt {
+            Some(interrupt) => {
+                peripherals.debugger().interrupt_dispatched(interrupt);
+                interrupt.vector_address()
             }
+            None => 0x0000,
         };
+    }
 
-        self.cycle += cycles;
+    /// Read from memory, which takes one M-cycle
+    fn read(&mut self, peripherals: &Peripherals, addr: u16) -> u8 {
+        let val = peripherals.read(self.cycle, addr);
+        self.cycle += 4;
+        val
+    }
 
-        self.registers.pc = pc;
+    /// Write to memory, which takes one M-cycle
+    fn write(&mut self, peripherals: &mut Peripherals, addr: u16, val: u8) {
+        peripherals.write(self.cycle, addr, val);
+        self.cycle += 4;
+    }
+
+    /// An M-cycle without memory access, e.g. for 16 bit arithmetic
+    fn tick(&mut self) {
+        self.cycle += 4;
+    }
+
+    /// Push the high byte, then the low byte
+    fn push(&mut self, peripherals: &mut Peripherals, val: u16) {
+        let [low, high] = val.to_le_bytes();
+
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(peripherals, self.registers.sp, high);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(peripherals, self.registers.sp, low);
+    }
+
+    fn pop(&mut self, peripherals: &Peripherals) -> u16 {
+        let low = self.read(peripherals, self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = self.read(peripherals, self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+
+        u16::from_le_bytes([low, high])
     }
 
-    fn load_operand8(&self, peripherals: &Peripherals, operand: Operand8) -> (u64, u8) {
+    fn load_operand8(&mut self, peripherals: &Peripherals, operand: Operand8) -> u8 {
         match operand {
-            Operand8::Register(register) => (0, self.registers.read(register)),
-            Operand8::IndirectHl => {
-                let addr = self.registers.hl();
-                let val = peripherals.read(self.cycle, addr);
-                (4, val)
-            }
-            Operand8::Immediate(immediate) => (4, immediate),
+            Operand8::Register(register) => self.registers.read(register),
+            Operand8::IndirectHl => self.read(peripherals, self.registers.hl()),
+            // Immediates are read together with the opcode
+            Operand8::Immediate(immediate) => immediate,
         }
     }
 
-    fn store_operand8(
-        &mut self,
-        peripherals: &mut Peripherals,
-        operand: Operand8,
-        value: u8,
-    ) -> u64 {
+    fn store_operand8(&mut self, peripherals: &mut Peripherals, operand: Operand8, value: u8) {
         match operand {
-            Operand8::Register(register) => {
-                self.registers.write(register, value);
-                0
-            }
-            Operand8::IndirectHl => {
-                let addr = self.registers.hl();
-                peripherals.write(self.cycle, addr, value);
-                4
-            }
+            Operand8::Register(register) => self.registers.write(register, value),
+            Operand8::IndirectHl => self.write(peripherals, self.registers.hl(), value),
             Operand8::Immediate(_) => panic!("Tried to store to immediate _value_"),
         }
     }
@@ -548,17 +803,37 @@ impl Cpu {
         }
     }
 
-    fn jump_destination(&self, pc: u16, destination: Destination) -> (u64, u16) {
+    fn jump_destination(&self, pc: u16, destination: Destination) -> u16 {
         match destination {
-            Destination::Absolute(addr) => (12, addr),
-            Destination::Relative(offset) => {
-                let addr = pc.wrapping_add_signed(offset as i16);
-                (8, addr)
-            }
-            Destination::Hl => {
-                let addr = self.registers.hl();
-                (0, addr)
-            }
+            Destination::Absolute(addr) => addr,
+            Destination::Relative(offset) => pc.wrapping_add_signed(offset as i16),
+            Destination::Hl => self.registers.hl(),
         }
     }
 }
+
+impl Snapshot for Cpu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.cycle);
+        self.registers.save_state(writer);
+        writer.put(&self.halted);
+        writer.put(&self.stopped);
+        writer.put(&self.locked_up);
+        writer.put(&self.interrupt_enable);
+        writer.put(&self.interrupt_enable_delayed);
+        writer.put(&self.halt_bug);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = reader.get()?;
+        self.registers.load_state(reader)?;
+        self.halted = reader.get()?;
+        self.stopped = reader.get()?;
+        self.locked_up = reader.get()?;
+        self.interrupt_enable = reader.get()?;
+        self.interrupt_enable_delayed = reader.get()?;
+        self.halt_bug = reader.get()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use std::cell::Cell;
+use std::fmt;
+
+use super::decoder::{
+    AddToSpDestination, ArithmeticLogic, BitOp, Condition, Destination, IncDecDirection,
+    Instruction, LoadMemoryDirection, LoadMemoryLocation, Operand16, Operand8, Register,
+    RotateCarry, RotateDirection,
+};
+use super::pc_reader::PcReader;
+
+/// A single decoded instruction in RGBDS syntax
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    /// The address of the first byte
+    pub addr: u16,
+    /// The opcode and its operands
+    pub bytes: Vec<u8>,
+    /// e.g. "ld a, [hl+]" or "jr nz, $0150"
+    pub text: String,
+    /// The number of cycles the instruction takes (if the branch is not taken)
+    pub cycles: u8,
+    /// The number of cycles of conditional jumps, calls and returns if the branch is taken
+    pub cycles_taken: Option<u8>,
+}
+
+impl DisassembledInstruction {
+    /// The length of the instruction in bytes
+    pub fn length(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Disassemble the instruction at `addr` using `memory` to read bytes
+pub(crate) fn disassemble_with(addr: u16, memory: &dyn Fn(u16) -> u8) -> DisassembledInstruction {
+    let mut pc = addr;
+
+    let inst = {
+        let mut reader = PcReader::new(&mut pc, memory);
+        let inst = Instruction::from_pc_reader(&mut reader);
+
+        // STOP is followed by a byte that is skipped
+        if let Instruction::Stop = inst {
+            reader.read_u8();
+        }
+
+        inst
+    };
+
+    let length = pc.wrapping_sub(addr);
+    let bytes = (0..length).map(|i| memory(addr.wrapping_add(i))).collect();
+    let (cycles, cycles_taken) = cycles(&inst);
+
+    DisassembledInstruction {
+        addr,
+        bytes,
+        text: format_instruction(&inst, pc),
+        cycles,
+        cycles_taken,
+    }
+}
+
+/// Disassemble the instruction at the start of `bytes`, which are located at `addr`.
+/// Returns `None` if `bytes` ends in the middle of the instruction.
+pub fn disassemble(bytes: &[u8], addr: u16) -> Option<DisassembledInstruction> {
+    let truncated = Cell::new(false);
+
+    let memory = |a: u16| {
+        let offset = a.wrapping_sub(addr) as usize;
+
+        match bytes.get(offset) {
+            Some(b) => *b,
+            None => {
+                truncated.set(true);
+                0
+            }
+        }
+    };
+
+    let inst = disassemble_with(addr, &memory);
+
+    (!truncated.get()).then_some(inst)
+}
+
+/// Disassemble `bytes`, which are located at `addr`, from start to end without
+/// following jumps (a linear sweep).
+/// Data is disassembled as if it were code, an instruction that is cut off by the
+/// end of `bytes` is output as `db` bytes.
+pub fn disassemble_linear(bytes: &[u8], addr: u16) -> LinearSweep<'_> {
+    LinearSweep {
+        bytes,
+        addr,
+        offset: 0,
+    }
+}
+
+/// The iterator returned by `disassemble_linear`
+pub struct LinearSweep<'a> {
+    bytes: &'a [u8],
+    addr: u16,
+    offset: usize,
+}
+
+impl Iterator for LinearSweep<'_> {
+    type Item = DisassembledInstruction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self
+            .bytes
+            .get(self.offset..)
+            .filter(|rest| !rest.is_empty())?;
+        let addr = self.addr.wrapping_add(self.offset as u16);
+
+        let inst = disassemble(rest, addr).unwrap_or_else(|| {
+            let bytes = rest.to_vec();
+            let list: Vec<String> = bytes.iter().map(|b| format!("${b:02x}")).collect();
+
+            DisassembledInstruction {
+                addr,
+                bytes,
+                text: format!("db {}", list.join(", ")),
+                cycles: 0,
+                cycles_taken: None,
+            }
+        });
+
+        self.offset += inst.length();
+
+        Some(inst)
+    }
+}
+
+/// The cycles an instruction takes if a branch is not taken / is taken
+fn cycles(inst: &Instruction) -> (u8, Option<u8>) {
+    match inst {
+        Instruction::Add16(_) => (8, None),
+        Instruction::AddToSp(AddToSpDestination::Sp, _) => (16, None),
+        Instruction::AddToSp(AddToSpDestination::Hl, _) => (12, None),
+        Instruction::ArithmeticLogic8(_, Operand8::Register(_)) => (4, None),
+        Instruction::ArithmeticLogic8(_, _) => (8, None),
+        Instruction::BitOp(BitOp::Test(_), Operand8::IndirectHl) => (12, None),
+        Instruction::BitOp(_, Operand8::IndirectHl) => (16, None),
+        Instruction::BitOp(_, _) => (8, None),
+        Instruction::Call(_, Condition::Always) => (24, None),
+        Instruction::Call(_, _) => (12, Some(24)),
+        Instruction::Ccf
+        | Instruction::Cpl
+        | Instruction::Daa
+        | Instruction::Di
+        | Instruction::Ei
+        | Instruction::Halt
+        | Instruction::Invalid(_)
+        | Instruction::Nop
+        | Instruction::RotateA(_, _)
+        | Instruction::Scf
+        | Instruction::Stop => (4, None),
+        Instruction::IncDec16(_, _) => (8, None),
+        Instruction::IncDec8(_, Operand8::IndirectHl) => (12, None),
+        Instruction::IncDec8(_, _) => (4, None),
+        Instruction::Jump(Destination::Hl, _) => (4, None),
+        Instruction::Jump(Destination::Relative(_), Condition::Always) => (12, None),
+        Instruction::Jump(Destination::Relative(_), _) => (8, Some(12)),
+        Instruction::Jump(Destination::Absolute(_), Condition::Always) => (16, None),
+        Instruction::Jump(Destination::Absolute(_), _) => (12, Some(16)),
+        Instruction::LoadHlToSp => (8, None),
+        Instruction::LoadImm16(_, _) => (12, None),
+        Instruction::LoadMemory(_, LoadMemoryLocation::ZeroPageImm(_)) => (12, None),
+        Instruction::LoadMemory(_, LoadMemoryLocation::Absolute(_)) => (16, None),
+        Instruction::LoadMemory(_, _) => (8, None),
+        Instruction::LoadSimple(Operand8::IndirectHl, Operand8::Immediate(_)) => (12, None),
+        Instruction::LoadSimple(Operand8::Register(_), Operand8::Register(_)) => (4, None),
+        Instruction::LoadSimple(_, _) => (8, None),
+        Instruction::LoadSpToImm(_) => (20, None),
+        Instruction::Pop(_) => (12, None),
+        Instruction::Push(_) => (16, None),
+        Instruction::Reset(_) => (16, None),
+        Instruction::Reti => (16, None),
+        Instruction::Return(Condition::Always) => (16, None),
+        Instruction::Return(_) => (8, Some(20)),
+    }
+}
+
+fn register(register: Register) -> &'static str {
+    match register {
+        Register::A => "a",
+        Register::B => "b",
+        Register::C => "c",
+        Register::D => "d",
+        Register::E => "e",
+        Register::H => "h",
+        Register::L => "l",
+    }
+}
+
+fn operand8(operand: &Operand8) -> String {
+    match operand {
+        Operand8::Register(r) => register(*r).to_string(),
+        Operand8::IndirectHl => "[hl]".to_string(),
+        Operand8::Immediate(imm) => format!("${imm:02x}"),
+    }
+}
+
+fn operand16(operand: &Operand16) -> &'static str {
+    match operand {
+        Operand16::Bc => "bc",
+        Operand16::De => "de",
+        Operand16::Hl => "hl",
+        Operand16::Sp => "sp",
+        Operand16::Af => "af",
+    }
+}
+
+fn condition(condition: &Condition) -> &'static str {
+    match condition {
+        Condition::Always => "",
+        Condition::NonZero => "nz, ",
+        Condition::NonCarry => "nc, ",
+        Condition::Zero => "z, ",
+        Condition::Carry => "c, ",
+    }
+}
+
+fn signed(val: i8) -> String {
+    match val < 0 {
+        true => format!("-${:02x}", val.unsigned_abs()),
+        false => format!("${val:02x}"),
+    }
+}
+
+/// Format `inst` in RGBDS syntax. `next_pc` is the address of the following
+/// instruction, which relative jumps are relative to.
+fn format_instruction(inst: &Instruction, next_pc: u16) -> String {
+    match inst {
+        Instruction::Add16(operand) => format!("add hl, {}", operand16(operand)),
+        Instruction::AddToSp(AddToSpDestination::Sp, offset) => {
+            format!("add sp, {}", signed(*offset))
+        }
+        Instruction::AddToSp(AddToSpDestination::Hl, offset) => match *offset < 0 {
+            true => format!("ld hl, sp-${:02x}", offset.unsigned_abs()),
+            false => format!("ld hl, sp+${offset:02x}"),
+        },
+        Instruction::ArithmeticLogic8(operation, operand) => {
+            let operand = operand8(operand);
+
+            match operation {
+                ArithmeticLogic::Add => format!("add a, {operand}"),
+                ArithmeticLogic::Adc => format!("adc a, {operand}"),
+                ArithmeticLogic::Sub => format!("sub {operand}"),
+                ArithmeticLogic::Sbc => format!("sbc a, {operand}"),
+                ArithmeticLogic::And => format!("and {operand}"),
+                ArithmeticLogic::Xor => format!("xor {operand}"),
+                ArithmeticLogic::Or => format!("or {operand}"),
+                ArithmeticLogic::Cp => format!("cp {operand}"),
+            }
+        }
+        Instruction::BitOp(operation, operand) => {
+            let operand = operand8(operand);
+
+            match operation {
+                BitOp::Rotate(RotateDirection::Left, RotateCarry::Through) => {
+                    format!("rlc {operand}")
+                }
+                BitOp::Rotate(RotateDirection::Right, RotateCarry::Through) => {
+                    format!("rrc {operand}")
+                }
+                BitOp::Rotate(RotateDirection::Left, RotateCarry::NotThrough) => {
+                    format!("rl {operand}")
+                }
+                BitOp::Rotate(RotateDirection::Right, RotateCarry::NotThrough) => {
+                    format!("rr {operand}")
+                }
+                BitOp::ShiftArithmetic(RotateDirection::Left) => format!("sla {operand}"),
+                BitOp::ShiftArithmetic(RotateDirection::Right) => format!("sra {operand}"),
+                BitOp::SwapNibbles => format!("swap {operand}"),
+                BitOp::ShiftRightLogical => format!("srl {operand}"),
+                BitOp::Test(bit) => format!("bit {}, {operand}", *bit as u8),
+                BitOp::Clear(bit) => format!("res {}, {operand}", *bit as u8),
+                BitOp::Set(bit) => format!("set {}, {operand}", *bit as u8),
+            }
+        }
+        Instruction::Call(destination, cond) => {
+            format!(
+                "call {}{}",
+                condition(cond),
+                destination_text(destination, next_pc)
+            )
+        }
+        Instruction::Ccf => "ccf".to_string(),
+        Instruction::Cpl => "cpl".to_string(),
+        Instruction::Daa => "daa".to_string(),
+        Instruction::Di => "di".to_string(),
+        Instruction::Ei => "ei".to_string(),
+        Instruction::IncDec16(direction, operand) => {
+            format!("{} {}", inc_dec(direction), operand16(operand))
+        }
+        Instruction::IncDec8(direction, operand) => {
+            format!("{} {}", inc_dec(direction), operand8(operand))
+        }
+        Instruction::Invalid(op) => format!("db ${op:02x}"),
+        Instruction::Halt => "halt".to_string(),
+        Instruction::Jump(Destination::Hl, _) => "jp hl".to_string(),
+        Instruction::Jump(destination @ Destination::Relative(_), cond) => {
+            format!(
+                "jr {}{}",
+                condition(cond),
+                destination_text(destination, next_pc)
+            )
+        }
+        Instruction::Jump(destination, cond) => {
+            format!(
+                "jp {}{}",
+                condition(cond),
+                destination_text(destination, next_pc)
+            )
+        }
+        Instruction::LoadHlToSp => "ld sp, hl".to_string(),
+        Instruction::LoadImm16(operand, imm) => {
+            format!("ld {}, ${imm:04x}", operand16(operand))
+        }
+        Instruction::LoadMemory(direction, location) => {
+            let (mnemonic, location) = match location {
+                LoadMemoryLocation::Bc => ("ld", "[bc]".to_string()),
+                LoadMemoryLocation::De => ("ld", "[de]".to_string()),
+                LoadMemoryLocation::HlInc => ("ld", "[hl+]".to_string()),
+                LoadMemoryLocation::HlDec => ("ld", "[hl-]".to_string()),
+                LoadMemoryLocation::ZeroPageC => ("ldh", "[c]".to_string()),
+                LoadMemoryLocation::ZeroPageImm(offset) => ("ldh", format!("[$ff{offset:02x}]")),
+                LoadMemoryLocation::Absolute(addr) => ("ld", format!("[${addr:04x}]")),
+            };
+
+            match direction {
+                LoadMemoryDirection::ToMemory => format!("{mnemonic} {location}, a"),
+                LoadMemoryDirection::FromMemory => format!("{mnemonic} a, {location}"),
+            }
+        }
+        Instruction::LoadSimple(destination, source) => {
+            format!("ld {}, {}", operand8(destination), operand8(source))
+        }
+        Instruction::LoadSpToImm(addr) => format!("ld [${addr:04x}], sp"),
+        Instruction::Nop => "nop".to_string(),
+        Instruction::Pop(operand) => format!("pop {}", operand16(operand)),
+        Instruction::Push(operand) => format!("push {}", operand16(operand)),
+        Instruction::Reset(slot) => format!("rst ${:02x}", (*slot as u8) * 8),
+        Instruction::Reti => "reti".to_string(),
+        Instruction::Return(Condition::Always) => "ret".to_string(),
+        Instruction::Return(cond) => format!("ret {}", condition(cond).trim_end_matches(", ")),
+        Instruction::RotateA(direction, carry) => match (direction, carry) {
+            (RotateDirection::Left, RotateCarry::Through) => "rlca".to_string(),
+            (RotateDirection::Right, RotateCarry::Through) => "rrca".to_string(),
+            (RotateDirection::Left, RotateCarry::NotThrough) => "rla".to_string(),
+            (RotateDirection::Right, RotateCarry::NotThrough) => "rra".to_string(),
+        },
+        Instruction::Scf => "scf".to_string(),
+        Instruction::Stop => "stop".to_string(),
+    }
+}
+
+fn inc_dec(direction: &IncDecDirection) -> &'static str {
+    match direction {
+        IncDecDirection::Inc => "inc",
+        IncDecDirection::Dec => "dec",
+    }
+}
+
+fn destination_text(destination: &Destination, next_pc: u16) -> String {
+    match destination {
+        Destination::Hl => "hl".to_string(),
+        Destination::Relative(offset) => {
+            format!("${:04x}", next_pc.wrapping_add(*offset as i16 as u16))
+        }
+        Destination::Absolute(addr) => format!("${addr:04x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions() {
+        let table: &[(&[u8], &str, u8, Option<u8>)] = &[
+            (&[0x00], "nop", 4, None),
+            (&[0x01, 0x34, 0x12], "ld bc, $1234", 12, None),
+            (&[0x08, 0x00, 0xc0], "ld [$c000], sp", 20, None),
+            (&[0x10, 0x00], "stop", 4, None),
+            (&[0x18, 0xfe], "jr $0100", 12, None),
+            (&[0x20, 0x05], "jr nz, $0107", 8, Some(12)),
+            (&[0x22], "ld [hl+], a", 8, None),
+            (&[0x36, 0x42], "ld [hl], $42", 12, None),
+            (&[0x41], "ld b, c", 4, None),
+            (&[0x76], "halt", 4, None),
+            (&[0x86], "add a, [hl]", 8, None),
+            (&[0xc0], "ret nz", 8, Some(20)),
+            (&[0xc3, 0x50, 0x01], "jp $0150", 16, None),
+            (&[0xc4, 0x00, 0x40], "call nz, $4000", 12, Some(24)),
+            (&[0xcb, 0x37], "swap a", 8, None),
+            (&[0xcb, 0x46], "bit 0, [hl]", 12, None),
+            (&[0xcb, 0xfe], "set 7, [hl]", 16, None),
+            (&[0xd3], "db $d3", 4, None),
+            (&[0xe0, 0x40], "ldh [$ff40], a", 12, None),
+            (&[0xe2], "ldh [c], a", 8, None),
+            (&[0xe8, 0xfe], "add sp, -$02", 16, None),
+            (&[0xea, 0x00, 0xc0], "ld [$c000], a", 16, None),
+            (&[0xf0, 0x44], "ldh a, [$ff44]", 12, None),
+            (&[0xf8, 0x05], "ld hl, sp+$05", 12, None),
+            (&[0xff], "rst $38", 16, None),
+        ];
+
+        for (bytes, text, cycles, cycles_taken) in table {
+            let inst = disassemble(bytes, 0x0100).unwrap();
+
+            assert_eq!(inst.addr, 0x0100);
+            assert_eq!(inst.bytes, *bytes, "{text}");
+            assert_eq!(inst.text, *text);
+            assert_eq!(inst.cycles, *cycles, "{text}");
+            assert_eq!(inst.cycles_taken, *cycles_taken, "{text}");
+        }
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        assert_eq!(disassemble(&[0xc3, 0x00], 0x0000), None);
+        assert_eq!(disassemble(&[0x10], 0x0000), None);
+        assert_eq!(disassemble(&[], 0x0000), None);
+    }
+
+    #[test]
+    fn linear_sweep_ends_in_data() {
+        let bytes = [0x00, 0x10, 0x00, 0x3e, 0x01, 0xc3, 0x00];
+        let sweep: Vec<_> = disassemble_linear(&bytes, 0x0100)
+            .map(|inst| (inst.addr, inst.length(), inst.text, inst.cycles))
+            .collect();
+
+        assert_eq!(
+            sweep,
+            [
+                (0x0100, 1, "nop".to_string(), 4),
+                (0x0101, 2, "stop".to_string(), 4),
+                (0x0103, 2, "ld a, $01".to_string(), 8),
+                (0x0105, 2, "db $c3, $00".to_string(), 0),
+            ]
+        );
+    }
+}
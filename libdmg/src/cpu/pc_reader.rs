@@ -1,29 +1,22 @@
-use crate::peripherals::Peripherals;
-
 pub struct PcReader<'a> {
-    cycle: u64,
     pc: &'a mut u16,
-    peripherals: &'a Peripherals,
+    memory: &'a dyn Fn(u16) -> u8,
 }
 
 impl<'a> PcReader<'a> {
-    pub(super) fn new(cycle: u64, pc: &'a mut u16, peripherals: &'a Peripherals) -> Self {
-        Self {
-            cycle,
-            pc,
-            peripherals,
-        }
+    pub(super) fn new(pc: &'a mut u16, memory: &'a dyn Fn(u16) -> u8) -> Self {
+        Self { pc, memory }
     }
 
     pub fn read_u8(&mut self) -> u8 {
-        let res = self.peripherals.read(self.cycle, *self.pc);
-        *self.pc += 1;
+        let res = (self.memory)(*self.pc);
+        *self.pc = self.pc.wrapping_add(1);
         res
     }
 
     pub fn read_u16(&mut self) -> u16 {
-        let res = self.peripherals.read_u16(self.cycle, *self.pc);
-        *self.pc += 2;
-        res
+        let low = self.read_u8();
+        let high = self.read_u8();
+        u16::from_le_bytes([low, high])
     }
 }
@@ -1,6 +1,7 @@
 use super::decoder::{Operand16, Register};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Registers {
     pub a: u8,
     pub f: u8,
@@ -154,3 +155,32 @@ impl Registers {
         self.flag(4)
     }
 }
+
+impl Snapshot for Registers {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&[
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ]);
+        writer.put(&self.sp);
+        writer.put(&self.pc);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let [a, f, b, c, d, e, h, l] = reader.get()?;
+
+        *self = Self {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: reader.get()?,
+            pc: reader.get()?,
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::ops::{Range, RangeInclusive};
+
+use log::warn;
+
+use super::registers::Registers;
+use crate::peripherals::Peripherals;
+
+/// Limits which instructions are written to the trace.
+/// An instruction is traced if it matches all filters that are set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only trace instructions at these addresses
+    pub pc: Option<RangeInclusive<u16>>,
+    /// Only trace instructions in this ROM bank
+    pub bank: Option<usize>,
+    /// Only trace instructions that start in this cycle window
+    pub cycles: Option<Range<u64>>,
+}
+
+pub(crate) struct Tracer {
+    sink: Box<dyn Write + Send>,
+    filter: TraceFilter,
+    failed: bool,
+}
+
+impl Tracer {
+    pub(crate) fn new(sink: Box<dyn Write + Send>, filter: TraceFilter) -> Self {
+        Self {
+            sink,
+            filter,
+            failed: false,
+        }
+    }
+
+    fn matches(&self, cycle: u64, pc: u16, peripherals: &Peripherals) -> bool {
+        let pc_matches = self.filter.pc.as_ref().is_none_or(|r| r.contains(&pc));
+        let cycle_matches = self
+            .filter
+            .cycles
+            .as_ref()
+            .is_none_or(|r| r.contains(&cycle));
+        let bank_matches = self
+            .filter
+            .bank
+            .is_none_or(|bank| peripherals.rom_bank_at(pc) == Some(bank));
+
+        pc_matches && cycle_matches && bank_matches
+    }
+
+    /// Write a line for the instruction the CPU is about to execute, in the format
+    /// used by e.g. Gameboy Doctor to compare traces between emulators.
+    pub(super) fn trace(&mut self, cycle: u64, registers: &Registers, peripherals: &Peripherals) {
+        let pc = registers.pc;
+
+        if self.failed || !self.matches(cycle, pc, peripherals) {
+            return;
+        }
+
+        let mem = |offset| peripherals.peek(cycle, pc.wrapping_add(offset));
+
+        let res = writeln!(
+            self.sink,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.a,
+            registers.f,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+            registers.sp,
+            pc,
+            mem(0),
+            mem(1),
+            mem(2),
+            mem(3),
+        );
+
+        if let Err(err) = res {
+            warn!("Failed to write trace, stopping it: {err}");
+            self.failed = true;
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        if let Err(err) = self.sink.flush() {
+            warn!("Failed to flush trace: {err}");
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+
+use crate::cpu::CpuState;
+use crate::peripherals::Interrupt;
+
+/// Identifies a breakpoint added using `Dmg::add_breakpoint`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BreakpointId(u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuRegister {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+}
+
+/// Holds if `register` contains `value`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterCondition {
+    pub register: CpuRegister,
+    pub value: u16,
+}
+
+impl RegisterCondition {
+    fn holds(&self, state: &CpuState) -> bool {
+        let [f, a] = state.af.to_le_bytes();
+        let [c, b] = state.bc.to_le_bytes();
+        let [e, d] = state.de.to_le_bytes();
+        let [l, h] = state.hl.to_le_bytes();
+
+        let value = match self.register {
+            CpuRegister::A => a as u16,
+            CpuRegister::F => f as u16,
+            CpuRegister::B => b as u16,
+            CpuRegister::C => c as u16,
+            CpuRegister::D => d as u16,
+            CpuRegister::E => e as u16,
+            CpuRegister::H => h as u16,
+            CpuRegister::L => l as u16,
+            CpuRegister::Af => state.af,
+            CpuRegister::Bc => state.bc,
+            CpuRegister::De => state.de,
+            CpuRegister::Hl => state.hl,
+            CpuRegister::Sp => state.sp,
+            CpuRegister::Pc => state.pc,
+        };
+
+        value == self.value
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop before the instruction at `pc` is executed.
+    /// With `bank` set this only applies if that ROM bank is mapped at `pc`,
+    /// with `condition` set only if the condition holds.
+    Pc {
+        pc: u16,
+        bank: Option<usize>,
+        condition: Option<RegisterCondition>,
+    },
+    /// Stop before an instruction is executed while the condition holds
+    Condition(RegisterCondition),
+    /// Stop when an address in `range` is accessed in one of the selected ways.
+    /// Reads and writes stop after the instruction that performed them,
+    /// executes before the instruction is executed.
+    Watch {
+        range: RangeInclusive<u16>,
+        read: bool,
+        write: bool,
+        execute: bool,
+    },
+    /// Stop once an interrupt (or any interrupt for `None`) was dispatched,
+    /// before the first instruction of the handler is executed.
+    Interrupt(Option<Interrupt>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub access: Access,
+    /// The value that was read or written, or the opcode for executes
+    pub value: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub id: BreakpointId,
+    /// The access that triggered a watchpoint
+    pub access: Option<MemoryAccess>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Debugger {
+    breakpoints: Vec<(BreakpointId, Breakpoint)>,
+    next_id: u32,
+    /// There are read or write watchpoints, which have to be checked on every memory access
+    watching: bool,
+    /// The first hit that was reported since the last `take_hit`
+    hit: Cell<Option<BreakpointHit>>,
+}
+
+impl Debugger {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn add(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+
+        self.breakpoints.push((id, breakpoint));
+        self.update_watching();
+
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: BreakpointId) -> bool {
+        let len = self.breakpoints.len();
+
+        self.breakpoints.retain(|(bp_id, _)| *bp_id != id);
+        self.update_watching();
+
+        self.breakpoints.len() != len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.update_watching();
+    }
+
+    pub(crate) fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, bp)| (*id, bp))
+    }
+
+    /// There are any breakpoints at all
+    pub(crate) fn is_active(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    fn update_watching(&mut self) {
+        self.watching = self.breakpoints.iter().any(|(_, bp)| {
+            matches!(
+                bp,
+                Breakpoint::Watch { read: true, .. } | Breakpoint::Watch { write: true, .. }
+            )
+        });
+    }
+
+    fn report(&self, hit: BreakpointHit) {
+        if self.hit.get().is_none() {
+            self.hit.set(Some(hit));
+        }
+    }
+
+    pub(crate) fn take_hit(&self) -> Option<BreakpointHit> {
+        self.hit.take()
+    }
+
+    fn watch(&self, addr: u16, access: Access, value: u8) {
+        let hit = self.breakpoints.iter().find(|(_, bp)| match bp {
+            Breakpoint::Watch {
+                range,
+                read,
+                write,
+                execute,
+            } => {
+                let selected = match access {
+                    Access::Read => *read,
+                    Access::Write => *write,
+                    Access::Execute => *execute,
+                };
+
+                selected && range.contains(&addr)
+            }
+            _ => false,
+        });
+
+        if let Some((id, _)) = hit {
+            self.report(BreakpointHit {
+                id: *id,
+                access: Some(MemoryAccess {
+                    addr,
+                    access,
+                    value,
+                }),
+            })
+        }
+    }
+
+    pub(crate) fn watch_read(&self, addr: u16, value: u8) {
+        if self.watching {
+            self.watch(addr, Access::Read, value);
+        }
+    }
+
+    pub(crate) fn watch_write(&self, addr: u16, value: u8) {
+        if self.watching {
+            self.watch(addr, Access::Write, value);
+        }
+    }
+
+    pub(crate) fn interrupt_dispatched(&self, interrupt: Interrupt) {
+        let hit = self.breakpoints.iter().find(|(_, bp)| match bp {
+            Breakpoint::Interrupt(filter) => filter.is_none_or(|i| i == interrupt),
+            _ => false,
+        });
+
+        if let Some((id, _)) = hit {
+            self.report(BreakpointHit {
+                id: *id,
+                access: None,
+            })
+        }
+    }
+
+    /// Check the breakpoints that apply before the instruction at `state.pc`
+    /// (with `opcode`) is executed while ROM `bank` is mapped there.
+    pub(crate) fn check_execute(&self, state: &CpuState, bank: Option<usize>, opcode: u8) {
+        for (id, bp) in self.breakpoints.iter() {
+            let hit = match bp {
+                Breakpoint::Pc {
+                    pc,
+                    bank: bp_bank,
+                    condition,
+                } => {
+                    *pc == state.pc
+                        && bp_bank.is_none_or(|b| Some(b) == bank)
+                        && condition.is_none_or(|c| c.holds(state))
+                }
+                Breakpoint::Condition(condition) => condition.holds(state),
+                _ => false,
+            };
+
+            if hit {
+                self.report(BreakpointHit {
+                    id: *id,
+                    access: None,
+                });
+                return;
+            }
+        }
+
+        self.watch(state.pc, Access::Execute, opcode);
+    }
+}
@@ -0,0 +1,23 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The boot ROM has to be exactly 256 bytes long
+    BootRomSize(usize),
+    /// The ROM is too short to contain a cartridge header
+    RomTooSmall(usize),
+    /// The save file is larger than the cartridge RAM (plus an RTC trailer)
+    SaveTooLarge(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BootRomSize(len) => write!(f, "boot ROM has {len} instead of 256 bytes"),
+            Self::RomTooSmall(len) => write!(f, "ROM with {len} bytes has no cartridge header"),
+            Self::SaveTooLarge(len) => write!(f, "save file with {len} bytes is too large"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,29 +1,422 @@
 mod cpu;
+mod debugger;
+mod error;
 mod peripherals;
+mod state;
 
-use cpu::Cpu;
+pub use cpu::{
+    disassemble, disassemble_linear, CpuState, DisassembledInstruction, LinearSweep, TraceFilter,
+};
+use cpu::{Cpu, Tracer};
+pub use debugger::{
+    Access, Breakpoint, BreakpointHit, BreakpointId, CpuRegister, MemoryAccess, RegisterCondition,
+};
+pub use error::Error;
 use peripherals::Peripherals;
-pub use peripherals::{Button, Cartridge};
+pub use peripherals::{
+    Button, Cartridge, CartridgeInfo, CartridgeType, CgbSupport, Interrupt, Licensee, RtcClock,
+};
+pub use state::StateError;
+use state::{Snapshot, StateReader, StateWriter};
+
+/// The number of cycles in a frame, used as frame length while the LCD is off
+const CYCLES_PER_FRAME: u64 = 70224;
 
 pub struct Dmg {
     cpu: Cpu,
     peripherals: Peripherals,
+    tracer: Option<Tracer>,
+}
+
+pub struct Frame<'a> {
+    /// 160x144 pixels, one shade of gray (0 to 3) per byte
+    pub framebuffer: &'a [u8],
+    /// False if the LCD was off and the framebuffer did not change
+    pub new_frame: bool,
+}
+
+/// Why a `Dmg::step_instruction`, `run_cycles`, `run_until_pc` or `run_until` call returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The instruction was executed, the cycles were run or the condition was met
+    TargetReached,
+    /// A breakpoint or watchpoint was hit
+    Breakpoint(BreakpointHit),
+    /// The CPU is halted and waits for an interrupt
+    Halted,
+    /// The CPU executed a STOP instruction and waits for joypad input.
+    /// Buttons pressed in the next `run_frame` call wake it up.
+    Stopped,
+    /// The CPU executed one of the illegal opcodes and froze.
+    /// It does not execute any further instructions or interrupts,
+    /// but the rest of the machine keeps running, e.g. in `run_frame` or `run_cycles`.
+    LockedUp,
 }
 
 impl Dmg {
-    pub fn new(bootrom: Vec<u8>, cartridge: Cartridge) -> Self {
-        Self {
+    /// Fails if the boot ROM is not 256 bytes long
+    pub fn new(bootrom: Vec<u8>, cartridge: Cartridge) -> Result<Self, Error> {
+        Ok(Self {
             cpu: Cpu::new(),
-            peripherals: Peripherals::new(bootrom, cartridge),
-        }
+            peripherals: Peripherals::new(bootrom, cartridge)?,
+            tracer: None,
+        })
     }
 
-    pub fn run_frame(&mut self, buttons: &[Button]) -> &[u8] {
+    /// Run the emulation until the start of the next vblank period,
+    /// when a complete frame is in the framebuffer.
+    /// While the LCD is off there is no vblank, so the emulation runs for
+    /// the length of a frame instead and `Frame::new_frame` is false.
+    /// A CPU that locked up on an illegal opcode does not stop the emulation,
+    /// the rest of the machine keeps running.
+    /// This can not fail at the moment. Like for the other run functions the `Result`
+    /// is kept for API stability, so that runtime errors can be added later.
+    pub fn run_frame(&mut self, buttons: &[Button]) -> Result<Frame<'_>, Error> {
         self.peripherals.buttons(buttons);
 
-        // TODO: make sure we run until vblank
-        self.cpu.run(&mut self.peripherals, 70224);
+        let deadline = self.cpu.cycle() + CYCLES_PER_FRAME;
+
+        let new_frame = loop {
+            let next_vblank = self.peripherals.next_vblank(self.cpu.cycle());
+            let halt_limit = if self.peripherals.lcd_enabled() {
+                next_vblank
+            } else {
+                deadline
+            };
+
+            self.cpu
+                .step(&mut self.peripherals, halt_limit, self.tracer.as_mut());
+
+            if self.cpu.cycle() >= next_vblank {
+                break true;
+            }
+
+            if !self.peripherals.lcd_enabled() && self.cpu.cycle() >= deadline {
+                break false;
+            }
+        };
+
+        Ok(Frame {
+            framebuffer: self.peripherals.framebuffer(self.cpu.cycle()),
+            new_frame,
+        })
+    }
+
+    /// The current CPU registers and execution state
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    /// Overwrite the CPU registers and execution state, e.g. to set up a test.
+    /// The `cycle` field is ignored, as time can not be changed.
+    /// Changing `stopped` stops or resumes the LCD and resets DIV like STOP does,
+    /// a pending EI or HALT bug from the previous state is dropped.
+    pub fn set_cpu_state(&mut self, state: &CpuState) {
+        self.cpu.set_state(state, &mut self.peripherals);
+    }
+
+    /// Read from memory like the CPU does at this point in time.
+    /// Reads do not have side effects, so this is safe to use for debugging.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.peripherals.peek(self.cpu.cycle(), addr)
+    }
+
+    /// Write to memory without side effects like bank switching, DMA transfers,
+    /// DIV resets, boot ROM unmapping, TIMA increments, STAT interrupts
+    /// or audio channels being triggered or disabled.
+    /// The LCD enable bit of LCDC and the APU power bit of NR52 are left unchanged.
+    /// Writes to 0x0000-0x7fff patch the ROM bank that is currently mapped there,
+    /// writes to 0xa000-0xbfff go to the mapped RAM bank, even if RAM access is disabled.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.peripherals.poke(self.cpu.cycle(), addr, val);
+    }
+
+    /// Disassemble the instruction at `addr` as the CPU would see it right now
+    pub fn disassemble_at(&self, addr: u16) -> DisassembledInstruction {
+        let cycle = self.cpu.cycle();
+        let memory = |addr| self.peripherals.peek(cycle, addr);
+
+        cpu::disassemble_with(addr, &memory)
+    }
+
+    /// Disassemble a complete ROM bank using a linear sweep.
+    /// Bank 0 is disassembled at 0x0000, all other banks at 0x4000.
+    /// Returns `None` if the ROM is not that large.
+    pub fn disassemble_rom_bank(&self, bank: usize) -> Option<LinearSweep<'_>> {
+        let data = self.peripherals.cartridge().rom_bank_data(bank)?;
+        let addr = if bank == 0 { 0x0000 } else { 0x4000 };
+
+        Some(disassemble_linear(data, addr))
+    }
+
+    /// The number of 16KiB ROM banks
+    pub fn rom_banks(&self) -> usize {
+        self.peripherals.cartridge().rom_banks()
+    }
+
+    /// Read from ROM `bank`, independent of the currently mapped bank.
+    /// `addr` can be given as offset into the bank or as address in
+    /// 0x0000-0x3fff or 0x4000-0x7fff, e.g. bank 5, 0x4123.
+    /// Returns `None` if the ROM is not that large.
+    pub fn peek_rom(&self, bank: usize, addr: u16) -> Option<u8> {
+        self.peripherals.cartridge().peek_rom(bank, addr)
+    }
+
+    /// Patch ROM `bank`. See `peek_rom` for the addressing.
+    /// Returns false if the ROM is not that large.
+    pub fn poke_rom(&mut self, bank: usize, addr: u16, val: u8) -> bool {
+        self.peripherals.cartridge_mut().poke_rom(bank, addr, val)
+    }
+
+    /// Switch the time source of the cartridge real-time clock (if there is one).
+    /// The clock keeps its current time and continues counting from there.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        let cycle = self.cpu.cycle();
+        self.peripherals
+            .cartridge_mut()
+            .set_rtc_clock_at(cycle, clock);
+    }
+
+    /// The number of 8KiB cartridge RAM banks
+    pub fn sram_banks(&self) -> usize {
+        self.peripherals.cartridge().ram_banks()
+    }
+
+    /// Read from cartridge RAM `bank`, independent of the currently mapped bank
+    /// and whether RAM access is enabled.
+    /// `addr` can be given as offset into the bank or as address in 0xa000-0xbfff.
+    /// Returns `None` if the RAM is not that large.
+    pub fn peek_sram(&self, bank: usize, addr: u16) -> Option<u8> {
+        self.peripherals.cartridge().peek_ram(bank, addr)
+    }
+
+    /// Write to cartridge RAM `bank`. See `peek_sram` for the addressing.
+    /// Returns false if the RAM is not that large.
+    pub fn poke_sram(&mut self, bank: usize, addr: u16, val: u8) -> bool {
+        self.peripherals.cartridge_mut().poke_ram(bank, addr, val)
+    }
+
+    /// Execute a single instruction or dispatch a single interrupt.
+    /// A halted CPU idles until the next interrupt instead.
+    /// Returns `StopReason::Halted` if the CPU is halted afterwards,
+    /// `StopReason::Stopped` if it is in STOP mode and `StopReason::LockedUp` if it froze.
+    /// An illegal opcode is not an error, it locks up the CPU like on hardware.
+    pub fn step_instruction(&mut self) -> Result<StopReason, Error> {
+        if self.cpu.locked_up() {
+            return Ok(StopReason::LockedUp);
+        }
+
+        if self.cpu.stopped_waiting(&self.peripherals) {
+            return Ok(StopReason::Stopped);
+        }
+
+        if self.cpu.halted_forever(&self.peripherals) {
+            return Ok(StopReason::Halted);
+        }
+
+        let reason = if let Some(hit) = self.step_checked(u64::MAX, false) {
+            StopReason::Breakpoint(hit)
+        } else if self.cpu.locked_up() {
+            StopReason::LockedUp
+        } else if self.cpu.stopped() {
+            StopReason::Stopped
+        } else if self.cpu.halted() {
+            StopReason::Halted
+        } else {
+            StopReason::TargetReached
+        };
+
+        Ok(reason)
+    }
+
+    /// Run the emulation for (at least) `cycles` cycles.
+    /// Instructions are not interrupted, so the emulation may run a few cycles longer.
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<StopReason, Error> {
+        let end_cycle = self.cpu.cycle() + cycles;
+        let mut first = true;
+
+        while self.cpu.cycle() < end_cycle {
+            if let Some(hit) = self.step_checked(end_cycle, !first) {
+                return Ok(StopReason::Breakpoint(hit));
+            }
+
+            first = false;
+        }
+
+        Ok(StopReason::TargetReached)
+    }
+
+    /// Run the emulation until the CPU is about to execute the instruction at `addr`.
+    /// At least one instruction is executed, even if the CPU already is at `addr`.
+    pub fn run_until_pc(&mut self, addr: u16) -> Result<StopReason, Error> {
+        self.run_until(|dmg| dmg.cpu.pc() == addr)
+    }
+
+    /// Run the emulation until `predicate` returns true.
+    /// The predicate is checked after every instruction.
+    /// Returns `StopReason::Halted` if the CPU halts and no interrupt could ever wake it up,
+    /// `StopReason::Stopped` if it waits for joypad input and `StopReason::LockedUp` if it froze.
+    pub fn run_until(
+        &mut self,
+        mut predicate: impl FnMut(&Self) -> bool,
+    ) -> Result<StopReason, Error> {
+        let mut first = true;
+
+        loop {
+            if self.cpu.locked_up() {
+                return Ok(StopReason::LockedUp);
+            }
+
+            if self.cpu.stopped_waiting(&self.peripherals) {
+                return Ok(StopReason::Stopped);
+            }
+
+            if self.cpu.halted_forever(&self.peripherals) {
+                return Ok(StopReason::Halted);
+            }
+
+            if let Some(hit) = self.step_checked(u64::MAX, !first) {
+                return Ok(StopReason::Breakpoint(hit));
+            }
+
+            if predicate(self) {
+                return Ok(StopReason::TargetReached);
+            }
+
+            first = false;
+        }
+    }
+
+    /// Execute a single step and report the breakpoints that were hit by it.
+    /// Breakpoints on the instruction itself are only checked if `check_execute` is set,
+    /// so that execution can be resumed after stopping at one.
+    fn step_checked(&mut self, halt_limit: u64, check_execute: bool) -> Option<BreakpointHit> {
+        let debugger = self.peripherals.debugger();
+
+        // Forget about hits that happened outside of a debugging session, e.g. in run_frame
+        debugger.take_hit();
+
+        let executing = !self.cpu.halted() && !self.cpu.stopped() && !self.cpu.locked_up();
+
+        if check_execute && debugger.is_active() && executing {
+            let pc = self.cpu.pc();
+            let bank = self.peripherals.rom_bank_at(pc);
+            let opcode = self.peripherals.peek(self.cpu.cycle(), pc);
+
+            debugger.check_execute(&self.cpu.state(), bank, opcode);
+
+            if let Some(hit) = debugger.take_hit() {
+                return Some(hit);
+            }
+        }
+
+        self.cpu
+            .step(&mut self.peripherals, halt_limit, self.tracer.as_mut());
+
+        self.peripherals.debugger().take_hit()
+    }
+
+    /// Write a line with the CPU state to `sink` before every executed instruction
+    /// that matches `filter`.
+    /// The lines look like "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02",
+    /// the format used by Gameboy Doctor and many other emulators.
+    pub fn start_trace(&mut self, sink: Box<dyn std::io::Write + Send>, filter: TraceFilter) {
+        self.stop_trace();
+        self.tracer = Some(Tracer::new(sink, filter));
+    }
+
+    pub fn stop_trace(&mut self) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.flush();
+        }
+    }
+
+    /// Add a breakpoint that stops `step_instruction`, `run_cycles` and the `run_until` functions.
+    /// Breakpoints do not affect `run_frame`.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        self.peripherals.debugger_mut().add(breakpoint)
+    }
+
+    /// Returns false if there is no breakpoint with this id
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        self.peripherals.debugger_mut().remove(id)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.peripherals.debugger_mut().clear()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.peripherals.debugger().breakpoints()
+    }
+
+    /// The cartridge RAM content in the common .sav file format,
+    /// which includes the RTC state for cartridges with a real-time clock.
+    /// Returns `None` for cartridges without a battery, as they do not persist anything.
+    pub fn save_data(&mut self) -> Option<Vec<u8>> {
+        let cycle = self.cpu.cycle();
+        self.peripherals.cartridge_mut().save_data(cycle)
+    }
+
+    /// The raw content of the cartridge RAM
+    pub fn sram(&self) -> &[u8] {
+        self.peripherals.cartridge().ram()
+    }
+
+    /// A counter that is incremented on every write to cartridge RAM.
+    /// Compare it to the value at the time of the last save to find out if
+    /// there are unsaved changes.
+    pub fn sram_generation(&self) -> u64 {
+        self.peripherals.cartridge().generation()
+    }
+
+    /// Returns true (once) if the game disabled cartridge RAM access after
+    /// writing to it since the last `save_data` call.
+    /// This is a good moment to persist `save_data`.
+    pub fn take_save_request(&mut self) -> bool {
+        self.peripherals.cartridge_mut().take_save_request()
+    }
+
+    /// Serialize the complete machine state.
+    /// The state can only be loaded into a `Dmg` running the same ROM.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new(self.peripherals.cartridge().rom_hash());
+
+        self.cpu.save_state(&mut writer);
+        self.peripherals.save_state(&mut writer);
+
+        writer.finish()
+    }
+
+    /// Restore a state created using `save_state`.
+    /// The current state is left untouched if the state can not be loaded.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state, self.peripherals.cartridge().rom_hash())?;
+
+        let mut cpu = self.cpu.clone();
+        let mut peripherals = self.peripherals.clone();
+
+        cpu.load_state(&mut reader)?;
+        peripherals.load_state(&mut reader)?;
+        reader.finish()?;
+        peripherals.check_state(cpu.cycle())?;
+
+        self.cpu = cpu;
+        self.peripherals = peripherals;
+
+        Ok(())
+    }
+
+    /// Enable audio output at `sample_rate` Hz (e.g. 48000) or disable it using `None`.
+    /// Audio output is disabled by default.
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.peripherals
+            .set_sample_rate(self.cpu.cycle(), sample_rate);
+    }
 
-        self.peripherals.framebuffer(self.cpu.cycle())
+    /// Drain the stereo `[left, right]` samples that were produced since the last call,
+    /// e.g. during the last `run_frame`.
+    pub fn audio_samples(&mut self) -> impl Iterator<Item = [i16; 2]> + '_ {
+        self.peripherals.audio_samples(self.cpu.cycle())
     }
 }
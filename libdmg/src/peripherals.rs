@@ -6,10 +6,17 @@ mod serial;
 mod timer;
 mod video;
 
+use crate::debugger::Debugger;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use crate::Error;
+
 pub use interrupts::{Interrupt, InterruptMask, InterruptSource};
 pub use joypad::Button;
-pub use memory::cartridge::Cartridge;
+pub use memory::cartridge::{
+    Cartridge, CartridgeInfo, CartridgeType, CgbSupport, Licensee, RtcClock,
+};
 
+#[derive(Clone)]
 pub struct Peripherals {
     bootrom: memory::bootrom::BootRom,
     cartridge: Cartridge,
@@ -22,12 +29,13 @@ pub struct Peripherals {
     bootrom_mapped: bool,
     dma_reg: u8,
     ie_reg: u8,
+    debugger: Debugger,
 }
 
 impl Peripherals {
-    pub(crate) fn new(bootrom: Vec<u8>, cartridge: Cartridge) -> Self {
-        Self {
-            bootrom: memory::bootrom::BootRom::new(bootrom),
+    pub(crate) fn new(bootrom: Vec<u8>, cartridge: Cartridge) -> Result<Self, Error> {
+        Ok(Self {
+            bootrom: memory::bootrom::BootRom::new(bootrom)?,
             cartridge,
             video: video::Video::new(),
             ram: memory::ram::Ram::new(),
@@ -38,7 +46,8 @@ impl Peripherals {
             bootrom_mapped: true,
             dma_reg: 0,
             ie_reg: 0,
-        }
+            debugger: Debugger::new(),
+        })
     }
 
     pub(crate) fn buttons(&mut self, buttons: &[Button]) {
@@ -49,19 +58,115 @@ impl Peripherals {
         self.video.framebuffer(cycle)
     }
 
+    pub(crate) fn lcd_enabled(&self) -> bool {
+        self.video.lcd_enabled()
+    }
+
+    /// Check the parts of a loaded state that depend on the CPU `cycle`
+    pub(crate) fn check_state(&self, cycle: u64) -> Result<(), StateError> {
+        self.video.check_state(cycle)
+    }
+
+    pub(crate) fn next_vblank(&self, cycle: u64) -> u64 {
+        self.video.next_vblank(cycle)
+    }
+
+    /// Enter the low-power mode of the STOP instruction.
+    /// DIV is reset and the PPU stops until `resume` is called.
+    /// Like `next_pending`, but only for the interrupts in `mask`.
+    /// Used with IE, as a halted CPU can not be woken up by disabled interrupts.
+    pub(crate) fn next_pending_masked(&self, cycle: u64, mask: InterruptMask) -> u64 {
+        let sources = [
+            (Interrupt::VBlank, self.video.next_vblank(cycle)),
+            (Interrupt::Lcd, self.video.next_stat_edge(cycle)),
+            (Interrupt::Timer, self.timer.next_pending(cycle)),
+            (Interrupt::Serial, self.serial.next_pending(cycle)),
+            (Interrupt::Joypad, self.joypad.next_pending(cycle)),
+        ];
+
+        sources
+            .into_iter()
+            .filter(|(interrupt, _)| mask.is_set(*interrupt))
+            .map(|(_, cycle)| cycle)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+
+    pub(crate) fn stop(&mut self, cycle: u64) {
+        self.reset_div(cycle);
+        self.video.stop(cycle);
+    }
+
+    pub(crate) fn resume(&mut self, cycle: u64) {
+        // The clock that drives DIV was stopped, so it still reads as zero
+        self.reset_div(cycle);
+        self.video.resume(cycle);
+    }
+
+    fn reset_div(&mut self, cycle: u64) {
+        // The APU frame sequencer is clocked from the same counter
+        self.audio.reset_div(cycle, self.timer.counter(cycle));
+        self.timer.write(cycle, 0xff04, 0);
+    }
+
+    /// One of the selected joypad input lines is low, which ends STOP mode
+    pub(crate) fn joypad_input(&self) -> bool {
+        self.joypad.read() & 0x0f != 0x0f
+    }
+
+    pub(crate) fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub(crate) fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// The ROM bank mapped at `addr`, if `addr` is in cartridge ROM
+    pub(crate) fn rom_bank_at(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x0000..=0x00ff if self.bootrom_mapped => None,
+            0x0000..=0x7fff => Some(self.cartridge.rom_bank(addr)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub(crate) fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, cycle: u64, sample_rate: Option<u32>) {
+        self.audio.set_sample_rate(cycle, sample_rate);
+    }
+
+    pub(crate) fn audio_samples(&mut self, cycle: u64) -> impl Iterator<Item = [i16; 2]> + '_ {
+        self.audio.samples(cycle)
+    }
+
+    /// A CPU write, which is reported to the debugger
     pub(crate) fn write(&mut self, cycle: u64, addr: u16, val: u8) {
+        self.debugger.watch_write(addr, val);
+        self.write_unwatched(cycle, addr, val);
+    }
+
+    fn write_unwatched(&mut self, cycle: u64, addr: u16, val: u8) {
         match addr {
             0x0000..=0x00ff if self.bootrom_mapped => {}
-            0x0000..=0x7fff => self.cartridge.write(addr, val),
+            0x0000..=0x7fff => self.cartridge.write(cycle, addr, val),
             0x8000..=0x9fff => self.video.write(cycle, addr, val),
-            0xa000..=0xbfff => self.cartridge.write(addr, val),
+            0xa000..=0xbfff => self.cartridge.write(cycle, addr, val),
             0xc000..=0xfdff => self.ram.write(addr, val),
             0xfe00..=0xfe9f => self.video.write(cycle, addr, val),
             0xfea0..=0xfeff => {}
             0xff00 => self.joypad.write(val),
             0xff01..=0xff02 => self.serial.write(cycle, addr, val),
             0xff03 => {}
-            0xff04..=0xff07 => self.timer.write(cycle, addr, val),
+            0xff04 => self.reset_div(cycle),
+            0xff05..=0xff07 => self.timer.write(cycle, addr, val),
             0xff08..=0xff0e => {}
             0xff0f => self.set_pending(cycle, val.into()),
             0xff10..=0xff3f => self.audio.write(cycle, addr, val),
@@ -77,8 +182,8 @@ impl Peripherals {
                     let src_addr = (val as u16) << 8 | (idx as u16);
                     let dst_addr = 0xfe00 | (idx as u16);
 
-                    let val = self.read(virtual_cycle, src_addr);
-                    self.write(virtual_cycle, dst_addr, val);
+                    let val = self.peek(virtual_cycle, src_addr);
+                    self.write_unwatched(virtual_cycle, dst_addr, val);
                 }
 
                 // Store the value, just in case somewone wants to read it
@@ -100,7 +205,16 @@ impl Peripherals {
         }
     }
 
+    /// A CPU read, which is reported to the debugger
     pub(crate) fn read(&self, cycle: u64, addr: u16) -> u8 {
+        let val = self.peek(cycle, addr);
+        self.debugger.watch_read(addr, val);
+        val
+    }
+
+    /// Read without reporting the access to the debugger.
+    /// Reads do not have side effects otherwise.
+    pub(crate) fn peek(&self, cycle: u64, addr: u16) -> u8 {
         match addr {
             0x0000..=0x00ff if self.bootrom_mapped => self.bootrom.read(addr),
             0x0000..=0x7fff => self.cartridge.read(addr),
@@ -114,7 +228,10 @@ impl Peripherals {
             0xff03 => 0,
             0xff04..=0xff07 => self.timer.read(cycle, addr),
             0xff08..=0xff0e => 0,
-            0xff0f => self.pending(cycle).into(),
+            0xff0f => {
+                // Only the lower five bits exist, the others read as 1
+                0b1110_0000 | u8::from(self.pending(cycle))
+            }
             0xff10..=0xff3f => self.audio.read(cycle, addr),
             0xff40..=0xff45 => self.video.read(cycle, addr),
             0xff46 => self.dma_reg,
@@ -127,17 +244,22 @@ impl Peripherals {
         }
     }
 
-    pub(crate) fn read_u16(&self, cycle: u64, addr: u16) -> u16 {
-        let low = self.read(cycle, addr);
-        let high = self.read(cycle, addr.wrapping_add(1));
-        u16::from_le_bytes([low, high])
-    }
-
-    pub(crate) fn write_u16(&mut self, cycle: u64, addr: u16, val: u16) {
-        let [low, high] = val.to_le_bytes();
-
-        self.write(cycle, addr, low);
-        self.write(cycle, addr.wrapping_add(1), high);
+    /// Write to memory like the CPU does, but without side effects like bank switching,
+    /// DMA transfers, DIV resets, boot ROM unmapping, TIMA increments, STAT interrupts
+    /// or audio channels being triggered or disabled.
+    /// The LCD and the APU can not be switched on or off this way.
+    /// Writes to ROM patch the currently mapped ROM bank.
+    pub(crate) fn poke(&mut self, cycle: u64, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x00ff if self.bootrom_mapped => self.bootrom.poke(addr, val),
+            0x0000..=0x7fff | 0xa000..=0xbfff => self.cartridge.poke(addr, val),
+            0xff04 | 0xff50 => {}
+            0xff05..=0xff07 => self.timer.poke(cycle, addr, val),
+            0xff10..=0xff3f => self.audio.poke(cycle, addr, val),
+            0xff40..=0xff45 => self.video.poke(cycle, addr, val),
+            0xff46 => self.dma_reg = val,
+            _ => self.write_unwatched(cycle, addr, val),
+        }
     }
 }
 
@@ -173,3 +295,33 @@ impl InterruptSource for Peripherals {
         cycles.into_iter().min().unwrap()
     }
 }
+
+impl Snapshot for Peripherals {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.cartridge.save_state(writer);
+        self.video.save_state(writer);
+        self.ram.save_state(writer);
+        self.joypad.save_state(writer);
+        self.serial.save_state(writer);
+        self.timer.save_state(writer);
+        self.audio.save_state(writer);
+        writer.put(&self.bootrom_mapped);
+        writer.put(&self.dma_reg);
+        writer.put(&self.ie_reg);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.cartridge.load_state(reader)?;
+        self.video.load_state(reader)?;
+        self.ram.load_state(reader)?;
+        self.joypad.load_state(reader)?;
+        self.serial.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.audio.load_state(reader)?;
+        self.bootrom_mapped = reader.get()?;
+        self.dma_reg = reader.get()?;
+        self.ie_reg = reader.get()?;
+
+        Ok(())
+    }
+}
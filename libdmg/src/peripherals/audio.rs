@@ -1,13 +1,463 @@
-pub struct Audio;
+mod envelope;
+mod length;
+mod noise;
+mod resampler;
+mod square;
+mod wave;
+
+use noise::Noise;
+use resampler::Resampler;
+use square::Square;
+use wave::Wave;
+
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// The frame sequencer is clocked by the falling edge of bit 4 of DIV
+const FRAME_SEQUENCER_PERIOD: u64 = 8192;
+const FRAME_SEQUENCER_BIT: u16 = 1 << 12;
+const CLOCK_RATE: u64 = 4194304;
+
+#[derive(Clone)]
+pub struct Audio {
+    cycle: u64,
+    div_base: u64,
+    power: bool,
+    frame_sequencer_step: u8,
+    next_frame_sequencer: u64,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    nr50: u8,
+    nr51: u8,
+    resampler: Option<Resampler>,
+}
 
 impl Audio {
     pub(crate) fn new() -> Self {
-        Self
+        Self {
+            cycle: 0,
+            div_base: 0,
+            power: false,
+            frame_sequencer_step: 0,
+            next_frame_sequencer: u64::MAX,
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            nr50: 0,
+            nr51: 0,
+            resampler: None,
+        }
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, cycle: u64, sample_rate: Option<u32>) {
+        self.run_until(cycle);
+        self.resampler = sample_rate.map(|rate| Resampler::new(CLOCK_RATE, rate, self.cycle));
+        self.update_output();
+    }
+
+    pub(crate) fn samples(&mut self, cycle: u64) -> impl Iterator<Item = [i16; 2]> + '_ {
+        self.run_until(cycle);
+
+        self.resampler
+            .as_mut()
+            .map(|resampler| {
+                resampler.flush(cycle);
+                resampler.samples()
+            })
+            .into_iter()
+            .flatten()
+    }
+
+    /// Mix the channel outputs according to the NR50/NR51 panning
+    /// and volume settings.
+    fn amplitude(&self) -> [f32; 2] {
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        let mut mix = [0.0f32; 2];
+
+        for (ch, output) in outputs.into_iter().enumerate() {
+            let output = output as f32 / 15.0;
+
+            if self.nr51 & (0b0001_0000 << ch) != 0 {
+                mix[0] += output;
+            }
+
+            if self.nr51 & (0b0000_0001 << ch) != 0 {
+                mix[1] += output;
+            }
+        }
+
+        let volume_left = ((self.nr50 >> 4) & 0b111) + 1;
+        let volume_right = (self.nr50 & 0b111) + 1;
+
+        [
+            mix[0] * (volume_left as f32) / 32.0,
+            mix[1] * (volume_right as f32) / 32.0,
+        ]
+    }
+
+    fn update_output(&mut self) {
+        let amplitude = self.amplitude();
+
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.set_amplitude(self.cycle, amplitude);
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+
+        if matches!(step, 0 | 2 | 4 | 6) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+
+        if matches!(step, 2 | 6) {
+            self.square1.clock_sweep();
+        }
+
+        if step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_sequencer_step = (step + 1) % 8;
+        self.next_frame_sequencer += FRAME_SEQUENCER_PERIOD;
+    }
+
+    fn next_event(&self) -> u64 {
+        [
+            self.next_frame_sequencer,
+            self.square1.next_tick(),
+            self.square2.next_tick(),
+            self.wave.next_tick(),
+            self.noise.next_tick(),
+        ]
+        .into_iter()
+        .min()
+        .unwrap()
     }
 
-    pub(crate) fn read(&self, _cycle: u64, _addr: u16) -> u8 {
-        0
+    fn run_until(&mut self, cycle: u64) {
+        loop {
+            let event = self.next_event();
+
+            if event >= cycle {
+                break;
+            }
+
+            self.cycle = event;
+
+            if self.next_frame_sequencer == event {
+                self.clock_frame_sequencer();
+            }
+
+            if self.square1.next_tick() == event {
+                self.square1.tick();
+            }
+
+            if self.square2.next_tick() == event {
+                self.square2.tick();
+            }
+
+            if self.wave.next_tick() == event {
+                self.wave.tick();
+            }
+
+            if self.noise.next_tick() == event {
+                self.noise.tick();
+            }
+
+            self.update_output();
+        }
+
+        self.cycle = self.cycle.max(cycle);
     }
 
-    pub(crate) fn write(&mut self, _cycle: u64, _addr: u16, _val: u8) {}
+    /// Number of times the length counters will be clocked before `cycle`.
+    /// Used to report channel status on NR52 reads without having to
+    /// advance the state of the APU.
+    fn length_clocks_until(&self, cycle: u64) -> u64 {
+        if self.next_frame_sequencer >= cycle {
+            return 0;
+        }
+
+        let steps = (cycle - self.next_frame_sequencer).div_ceil(FRAME_SEQUENCER_PERIOD);
+
+        match self.frame_sequencer_step % 2 {
+            0 => steps.div_ceil(2),
+            _ => steps / 2,
+        }
+    }
+
+    fn power_on(&mut self) {
+        let periods = (self.cycle - self.div_base) / FRAME_SEQUENCER_PERIOD + 1;
+
+        self.power = true;
+        self.frame_sequencer_step = 0;
+        self.next_frame_sequencer = self.div_base + periods * FRAME_SEQUENCER_PERIOD;
+    }
+
+    /// Handle a write to DIV, which had the value `counter` before being reset
+    pub(crate) fn reset_div(&mut self, cycle: u64, counter: u16) {
+        self.run_until(cycle);
+
+        self.div_base = self.cycle;
+
+        if !self.power {
+            return;
+        }
+
+        // Resetting DIV while the frame sequencer bit is set is a falling edge
+        if counter & FRAME_SEQUENCER_BIT != 0 {
+            self.next_frame_sequencer = self.cycle;
+            self.clock_frame_sequencer();
+        } else {
+            self.next_frame_sequencer = self.cycle + FRAME_SEQUENCER_PERIOD;
+        }
+
+        self.update_output();
+    }
+
+    fn power_off(&mut self) {
+        self.power = false;
+        self.next_frame_sequencer = u64::MAX;
+        self.square1.power_off();
+        self.square2.power_off();
+        self.wave.power_off();
+        self.noise.power_off();
+        self.nr50 = 0;
+        self.nr51 = 0;
+    }
+
+    /// Channel 1 can also be disabled by a sweep overflow, which depends on
+    /// every sweep calculation before it. Replay the frame sequencer clocks
+    /// on a copy of the channel until the outcome is known.
+    fn square1_active(&self, cycle: u64) -> bool {
+        let mut square1 = self.square1.clone();
+        let mut step = self.frame_sequencer_step;
+        let mut next = self.next_frame_sequencer;
+
+        while next < cycle && square1.enabled() {
+            let length = square1.length();
+
+            if (!length.enable() || length.counter() == 0) && square1.sweep_settled() {
+                break;
+            }
+
+            if matches!(step, 0 | 2 | 4 | 6) {
+                square1.clock_length();
+            }
+
+            if matches!(step, 2 | 6) {
+                square1.clock_sweep();
+            }
+
+            step = (step + 1) % 8;
+            next += FRAME_SEQUENCER_PERIOD;
+        }
+
+        square1.enabled()
+    }
+
+    fn channel_status(&self, cycle: u64) -> u8 {
+        let length_clocks = self.length_clocks_until(cycle);
+
+        let active = |enabled: bool, length: &length::Length| {
+            let expired = length.enable() && (length.counter() as u64) <= length_clocks;
+            enabled && !expired
+        };
+
+        let ch1 = self.square1_active(cycle);
+        let ch2 = active(self.square2.enabled(), self.square2.length());
+        let ch3 = active(self.wave.enabled(), self.wave.length());
+        let ch4 = active(self.noise.enabled(), self.noise.length());
+
+        (ch4 as u8) << 3 | (ch3 as u8) << 2 | (ch2 as u8) << 1 | (ch1 as u8)
+    }
+
+    pub(crate) fn read(&self, cycle: u64, addr: u16) -> u8 {
+        match addr {
+            0xff10..=0xff14 => self.square1.read(addr - 0xff10),
+            0xff15..=0xff19 => self.square2.read(addr - 0xff15),
+            0xff1a..=0xff1e => self.wave.read(addr - 0xff1a),
+            0xff1f..=0xff23 => self.noise.read(addr - 0xff1f),
+            0xff24 => self.nr50,
+            0xff25 => self.nr51,
+            0xff26 => (self.power as u8) << 7 | 0b0111_0000 | self.channel_status(cycle),
+            0xff27..=0xff2f => 0xff,
+            0xff30..=0xff3f => self.wave.read_ram(cycle, addr),
+            _ => panic!("Address {addr} is not in audio range"),
+        }
+    }
+
+    pub(crate) fn write(&mut self, cycle: u64, addr: u16, val: u8) {
+        self.run_until(cycle);
+        self.write_register(cycle, addr, val);
+        self.update_output();
+    }
+
+    /// Set a register without side effects like disabling or triggering channels.
+    /// NR52 can not be poked, as it switches the APU on and off.
+    pub(crate) fn poke(&mut self, cycle: u64, addr: u16, val: u8) {
+        self.run_until(cycle);
+
+        match addr {
+            0xff10..=0xff14 if self.power => self.square1.poke(cycle, addr - 0xff10, val),
+            0xff15..=0xff19 if self.power => self.square2.poke(cycle, addr - 0xff15, val),
+            0xff1a..=0xff1e if self.power => self.wave.poke(cycle, addr - 0xff1a, val),
+            0xff1f..=0xff23 if self.power => self.noise.poke(cycle, addr - 0xff1f, val),
+            0xff26 => {}
+            _ => self.write_register(cycle, addr, val),
+        }
+
+        self.update_output();
+    }
+
+    fn write_register(&mut self, cycle: u64, addr: u16, val: u8) {
+        // The length counters are clocked on even frame sequencer steps.
+        // If the next step does not clock them some writes to NRx4 do so.
+        let extra_length_clock = self.frame_sequencer_step % 2 == 1;
+
+        if !self.power {
+            // While powered off only the length counters (on DMG) and
+            // the wave RAM can be written to. And NR52 of course.
+            match addr {
+                0xff11 => self.square1.write_length(val),
+                0xff16 => self.square2.write_length(val),
+                0xff1b => self.wave.write_length(val),
+                0xff20 => self.noise.write_length(val),
+                0xff26 if (val & 0b1000_0000) != 0 => self.power_on(),
+                0xff30..=0xff3f => self.wave.write_ram(cycle, addr, val),
+                _ => {}
+            }
+
+            return;
+        }
+
+        match addr {
+            0xff10..=0xff14 => self
+                .square1
+                .write(cycle, addr - 0xff10, val, extra_length_clock),
+            0xff15..=0xff19 => self
+                .square2
+                .write(cycle, addr - 0xff15, val, extra_length_clock),
+            0xff1a..=0xff1e => self
+                .wave
+                .write(cycle, addr - 0xff1a, val, extra_length_clock),
+            0xff1f..=0xff23 => self
+                .noise
+                .write(cycle, addr - 0xff1f, val, extra_length_clock),
+            0xff24 => {
+                self.nr50 = val;
+            }
+            0xff25 => {
+                self.nr51 = val;
+            }
+            0xff26 if (val & 0b1000_0000) == 0 => self.power_off(),
+            0xff26 => {}
+            0xff27..=0xff2f => {}
+            0xff30..=0xff3f => self.wave.write_ram(cycle, addr, val),
+            _ => panic!("Address {addr} is not in audio range"),
+        }
+    }
+}
+
+impl Snapshot for Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.cycle);
+        writer.put(&self.div_base);
+        writer.put(&self.power);
+        writer.put(&self.frame_sequencer_step);
+        writer.put(&self.next_frame_sequencer);
+        self.square1.save_state(writer);
+        self.square2.save_state(writer);
+        self.wave.save_state(writer);
+        self.noise.save_state(writer);
+        writer.put(&self.nr50);
+        writer.put(&self.nr51);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = reader.get()?;
+        self.div_base = reader.get()?;
+        self.power = reader.get()?;
+        self.frame_sequencer_step = reader.get::<u8>()? % 8;
+        self.next_frame_sequencer = reader.get()?;
+        self.square1.load_state(reader)?;
+        self.square2.load_state(reader)?;
+        self.wave.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.nr50 = reader.get()?;
+        self.nr51 = reader.get()?;
+
+        // DIV was reset before the APU ran up to its current cycle
+        if self.cycle < self.div_base {
+            return Err(StateError::Invalid("APU cycle"));
+        }
+
+        // The resampler only holds host side output, restart it at the restored cycle
+        let sample_rate = self.resampler.as_ref().map(Resampler::sample_rate);
+        self.resampler = sample_rate.map(|rate| Resampler::new(CLOCK_RATE, rate, self.cycle));
+        self.update_output();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(audio: &Audio) -> Result<(), StateError> {
+        let mut writer = StateWriter::new(0);
+        audio.save_state(&mut writer);
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data, 0)?;
+        Audio::new().load_state(&mut reader)
+    }
+
+    #[test]
+    fn state_with_div_reset_after_apu_cycle_is_rejected() {
+        let mut audio = Audio::new();
+        audio.reset_div(1000, 0);
+        assert_eq!(reload(&audio), Ok(()));
+
+        audio.div_base = 2000;
+        assert_eq!(reload(&audio), Err(StateError::Invalid("APU cycle")));
+    }
+
+    #[test]
+    fn sweep_overflow_shows_in_channel_status() {
+        let mut audio = Audio::new();
+        audio.write(4, 0xff26, 0x80);
+
+        // Sweep every 128 Hz with shift 1: the calculation on trigger still
+        // fits, the second one after the first sweep clock overflows.
+        let frequency: u16 = 1300;
+        audio.write(4, 0xff10, 0x11);
+        audio.write(4, 0xff12, 0xf0);
+        audio.write(4, 0xff13, frequency as u8);
+        audio.write(4, 0xff14, 0x80 | (frequency >> 8) as u8);
+        assert_eq!(audio.read(4, 0xff26), 0xf1);
+
+        // The frame sequencer steps 0 and 1 do not clock the sweep, step 2 does
+        let sweep_clock = 3 * FRAME_SEQUENCER_PERIOD;
+        assert_eq!(audio.read(sweep_clock, 0xff26), 0xf1);
+        assert_eq!(audio.read(sweep_clock + 1, 0xff26), 0xf0);
+
+        audio.write(sweep_clock + 1, 0xff24, 0x77);
+        assert_eq!(audio.read(sweep_clock + 1, 0xff26), 0xf0);
+    }
 }
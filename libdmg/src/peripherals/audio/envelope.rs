@@ -0,0 +1,87 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+pub(super) struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub(super) fn new() -> Self {
+        Self {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub(super) fn read(&self) -> u8 {
+        self.initial_volume << 4 | (self.increase as u8) << 3 | self.period
+    }
+
+    pub(super) fn write(&mut self, val: u8) {
+        self.initial_volume = val >> 4;
+        self.increase = (val & 0b0000_1000) != 0;
+        self.period = val & 0b0000_0111;
+    }
+
+    /// The DAC of a channel with an envelope is powered as long as
+    /// any of the upper five bits of NRx2 are set.
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub(super) fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub(super) fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.period;
+
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            }
+
+            if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.read());
+        writer.put(&self.volume);
+        writer.put(&self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.write(reader.get()?);
+        self.volume = reader.get()?;
+        self.timer = reader.get()?;
+
+        if self.volume > 15 {
+            return Err(StateError::Invalid("envelope volume"));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+pub(super) struct Length {
+    max: u16,
+    counter: u16,
+    enable: bool,
+}
+
+impl Length {
+    pub(super) fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enable: false,
+        }
+    }
+
+    pub(super) fn load(&mut self, val: u8) {
+        self.counter = self.max - (val as u16);
+    }
+
+    pub(super) fn enable(&self) -> bool {
+        self.enable
+    }
+
+    pub(super) fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    pub(super) fn set_enable(&mut self, enable: bool) {
+        self.enable = enable;
+    }
+
+    /// Returns true if the counter ran out and the channel should be disabled
+    pub(super) fn clock(&mut self) -> bool {
+        if self.enable && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    /// Handle the length related parts of a NRx4 write.
+    /// `extra_clock` is set when the frame sequencer is in the half of its period
+    /// that does not clock the length counter next, which triggers some obscure
+    /// extra clocking behaviour on hardware.
+    /// Returns true if the channel should be disabled.
+    pub(super) fn write_control(&mut self, enable: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enable;
+        self.enable = enable;
+
+        let mut disable = false;
+
+        if extra_clock && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+
+            if enable && extra_clock {
+                self.counter -= 1;
+            }
+        }
+
+        disable
+    }
+}
+
+impl Snapshot for Length {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.counter);
+        writer.put(&self.enable);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.counter = reader.get()?;
+        self.enable = reader.get()?;
+
+        if self.counter > self.max {
+            return Err(StateError::Invalid("length counter"));
+        }
+
+        Ok(())
+    }
+}
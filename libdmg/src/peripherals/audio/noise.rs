@@ -0,0 +1,200 @@
+use super::envelope::Envelope;
+use super::length::Length;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+const DIVISORS: [u64; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Clone)]
+pub(super) struct Noise {
+    length: Length,
+    envelope: Envelope,
+    shift: u8,
+    narrow: bool,
+    divisor_code: u8,
+    lfsr: u16,
+    enabled: bool,
+    next_tick: u64,
+}
+
+impl Noise {
+    pub(super) fn new() -> Self {
+        Self {
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            shift: 0,
+            narrow: false,
+            divisor_code: 0,
+            lfsr: 0x7fff,
+            enabled: false,
+            next_tick: u64::MAX,
+        }
+    }
+
+    pub(super) fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Self::new();
+        self.length = length;
+    }
+
+    /// Shift values of 14 and 15 stop the LFSR from being clocked at all
+    fn period(&self) -> Option<u64> {
+        (self.shift < 14).then(|| DIVISORS[self.divisor_code as usize] << self.shift)
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        ((!self.lfsr & 1) as u8) * self.envelope.volume()
+    }
+
+    pub(super) fn length(&self) -> &Length {
+        &self.length
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.next_tick = u64::MAX;
+    }
+
+    pub(super) fn next_tick(&self) -> u64 {
+        self.next_tick
+    }
+
+    pub(super) fn tick(&mut self) {
+        let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (xor << 14);
+
+        if self.narrow {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+        }
+
+        self.next_tick = match self.period() {
+            Some(period) => self.next_tick + period,
+            None => u64::MAX,
+        };
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.disable();
+        }
+    }
+
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => 0xff,
+            1 => 0xff,
+            2 => self.envelope.read(),
+            3 => self.shift << 4 | (self.narrow as u8) << 3 | self.divisor_code,
+            4 => (self.length.enable() as u8) << 6 | 0b1011_1111,
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0b0011_1111);
+    }
+
+    /// Set a register without disabling the channel, clocking the length counter
+    /// or triggering the channel
+    pub(super) fn poke(&mut self, cycle: u64, reg: u16, val: u8) {
+        match reg {
+            2 => self.envelope.write(val),
+            4 => self.length.set_enable((val & 0b0100_0000) != 0),
+            _ => self.write(cycle, reg, val, false),
+        }
+    }
+
+    pub(super) fn write(&mut self, cycle: u64, reg: u16, val: u8, extra_length_clock: bool) {
+        match reg {
+            1 => self.write_length(val),
+            2 => {
+                self.envelope.write(val);
+
+                if !self.envelope.dac_enabled() {
+                    self.disable();
+                }
+            }
+            3 => {
+                self.shift = val >> 4;
+                self.narrow = (val & 0b0000_1000) != 0;
+                self.divisor_code = val & 0b0000_0111;
+
+                // Leaving the stalled shift settings restarts the LFSR clock
+                if self.enabled && self.next_tick == u64::MAX {
+                    if let Some(period) = self.period() {
+                        self.next_tick = cycle + period;
+                    }
+                }
+            }
+            4 => {
+                let trigger = (val & 0b1000_0000) != 0;
+                let length_enable = (val & 0b0100_0000) != 0;
+
+                if self
+                    .length
+                    .write_control(length_enable, trigger, extra_length_clock)
+                {
+                    self.disable();
+                }
+
+                if trigger {
+                    self.trigger(cycle);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self, cycle: u64) {
+        self.envelope.trigger();
+        self.lfsr = 0x7fff;
+
+        if self.envelope.dac_enabled() {
+            self.enabled = true;
+            self.next_tick = match self.period() {
+                Some(period) => cycle + period,
+                None => u64::MAX,
+            };
+        } else {
+            self.disable();
+        }
+    }
+}
+
+impl Snapshot for Noise {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.put(&self.read(3));
+        writer.put(&self.lfsr);
+        writer.put(&self.enabled);
+        writer.put(&self.next_tick);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+
+        let reg: u8 = reader.get()?;
+        self.shift = reg >> 4;
+        self.narrow = (reg & 0b0000_1000) != 0;
+        self.divisor_code = reg & 0b0000_0111;
+
+        self.lfsr = reader.get::<u16>()? & 0x7fff;
+        self.enabled = reader.get()?;
+        self.next_tick = reader.get()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::f32::consts::PI;
+
+/// Sub-sample resolution of the band-limited step positions
+const PHASES: usize = 32;
+const TAPS: usize = 16;
+
+/// Fraction of the output nyquist frequency to let pass
+const CUTOFF: f32 = 0.9;
+
+/// The DMG output is AC coupled via a capacitor, which this
+/// charge factor per input cycle models.
+const HIGHPASS_CHARGE: f64 = 0.999958;
+
+/// Converts amplitude changes that happen at specific cycles into
+/// samples at a fixed output rate using band-limited steps.
+#[derive(Clone)]
+pub(super) struct Resampler {
+    clock_rate: u64,
+    sample_rate: u64,
+    kernel: Box<[[f32; TAPS]; PHASES]>,
+    highpass_charge: f32,
+    buffer_start: u64,
+    deltas: Vec<[f32; 2]>,
+    amplitude: [f32; 2],
+    integrator: [f32; 2],
+    capacitor: [f32; 2],
+    samples: Vec<[i16; 2]>,
+}
+
+fn kernel() -> Box<[[f32; TAPS]; PHASES]> {
+    let mut kernel = Box::new([[0.0; TAPS]; PHASES]);
+
+    for (phase, taps) in kernel.iter_mut().enumerate() {
+        let offset = phase as f32 / PHASES as f32;
+
+        for (tap, coefficient) in taps.iter_mut().enumerate() {
+            let x = tap as f32 - (TAPS / 2) as f32 - offset;
+
+            let sinc = match x * CUTOFF {
+                0.0 => 1.0,
+                xc => (PI * xc).sin() / (PI * xc),
+            };
+
+            // Blackman window spanning all taps
+            let n = (x + (TAPS / 2) as f32) / TAPS as f32;
+            let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+
+            *coefficient = sinc * window;
+        }
+
+        // Make sure every step ends up at exactly the requested amplitude
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|c| *c /= sum);
+    }
+
+    kernel
+}
+
+impl Resampler {
+    pub(super) fn new(clock_rate: u64, sample_rate: u32, cycle: u64) -> Self {
+        let sample_rate = sample_rate as u64;
+        let cycles_per_sample = clock_rate as f64 / sample_rate as f64;
+
+        let mut res = Self {
+            clock_rate,
+            sample_rate,
+            kernel: kernel(),
+            highpass_charge: HIGHPASS_CHARGE.powf(cycles_per_sample) as f32,
+            buffer_start: 0,
+            deltas: Vec::new(),
+            amplitude: [0.0; 2],
+            integrator: [0.0; 2],
+            capacitor: [0.0; 2],
+            samples: Vec::new(),
+        };
+
+        res.buffer_start = res.sample_time(cycle) / PHASES as u64;
+
+        res
+    }
+
+    /// Position of `cycle` in output samples, in units of 1/PHASES samples
+    fn sample_time(&self, cycle: u64) -> u64 {
+        let time = (cycle as u128) * (self.sample_rate as u128) * (PHASES as u128)
+            / (self.clock_rate as u128);
+
+        time as u64
+    }
+
+    /// Set the output amplitude from `cycle` onwards
+    pub(super) fn set_amplitude(&mut self, cycle: u64, amplitude: [f32; 2]) {
+        if amplitude == self.amplitude {
+            return;
+        }
+
+        let delta = [
+            amplitude[0] - self.amplitude[0],
+            amplitude[1] - self.amplitude[1],
+        ];
+
+        self.amplitude = amplitude;
+
+        let time = self.sample_time(cycle);
+        let offset = (time / PHASES as u64).saturating_sub(self.buffer_start) as usize;
+        let phase = (time % PHASES as u64) as usize;
+
+        if self.deltas.len() < offset + TAPS {
+            self.deltas.resize(offset + TAPS, [0.0; 2]);
+        }
+
+        for (dst, coefficient) in self.deltas[offset..].iter_mut().zip(&self.kernel[phase]) {
+            dst[0] += delta[0] * coefficient;
+            dst[1] += delta[1] * coefficient;
+        }
+    }
+
+    /// Turn all deltas that can no longer change (because they are before `cycle`)
+    /// into output samples.
+    pub(super) fn flush(&mut self, cycle: u64) {
+        let end = self.sample_time(cycle) / PHASES as u64;
+        let count = end.saturating_sub(self.buffer_start) as usize;
+
+        if self.deltas.len() < count {
+            self.deltas.resize(count, [0.0; 2]);
+        }
+
+        for delta in self.deltas.drain(..count) {
+            let mut sample = [0i16; 2];
+
+            for ch in 0..2 {
+                self.integrator[ch] += delta[ch];
+
+                let out = self.integrator[ch] - self.capacitor[ch];
+                self.capacitor[ch] = self.integrator[ch] - out * self.highpass_charge;
+
+                sample[ch] = (out * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+
+            self.samples.push(sample);
+        }
+
+        self.buffer_start += count as u64;
+    }
+
+    pub(super) fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    pub(super) fn samples(&mut self) -> std::vec::Drain<'_, [i16; 2]> {
+        self.samples.drain(..)
+    }
+}
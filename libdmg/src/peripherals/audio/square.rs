@@ -0,0 +1,366 @@
+use super::envelope::Envelope;
+use super::length::Length;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+#[derive(Clone)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Self {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow: 0,
+            enabled: false,
+            negate_used: false,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        self.period << 4 | (self.negate as u8) << 3 | self.shift
+    }
+
+    /// Returns true if the channel should be disabled
+    fn write(&mut self, val: u8) -> bool {
+        self.period = (val >> 4) & 0b0000_0111;
+        self.negate = (val & 0b0000_1000) != 0;
+        self.shift = val & 0b0000_0111;
+
+        // Leaving negate mode after at least one calculation was made using it
+        // disables the channel.
+        self.negate_used && !self.negate
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = match self.period {
+            0 => 8,
+            p => p,
+        };
+    }
+
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+
+        let frequency = if self.negate {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+
+        (frequency <= 2047).then_some(frequency)
+    }
+
+    /// Returns true if clocking the sweep can no longer disable the channel
+    fn settled(&self) -> bool {
+        // A decreasing frequency can not overflow
+        if !self.enabled || self.period == 0 || self.negate {
+            return true;
+        }
+
+        let frequency = self.shadow + (self.shadow >> self.shift);
+
+        match self.shift {
+            0 => frequency <= 2047,
+            _ => frequency == self.shadow,
+        }
+    }
+
+    /// Returns `None` if the channel should be disabled
+    fn trigger(&mut self, frequency: u16) -> Option<()> {
+        self.shadow = frequency;
+        self.negate_used = false;
+        self.reload_timer();
+        self.enabled = self.period != 0 || self.shift != 0;
+
+        if self.shift != 0 {
+            self.calculate()?;
+        }
+
+        Some(())
+    }
+
+    /// Returns the new channel frequency (if any) or `None` if the channel should be disabled
+    fn clock(&mut self) -> Option<Option<u16>> {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer != 0 {
+            return Some(None);
+        }
+
+        self.reload_timer();
+
+        if !self.enabled || self.period == 0 {
+            return Some(None);
+        }
+
+        let frequency = self.calculate()?;
+
+        if self.shift == 0 {
+            return Some(None);
+        }
+
+        self.shadow = frequency;
+        self.calculate()?;
+
+        Some(Some(frequency))
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct Square {
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_position: u8,
+    length: Length,
+    envelope: Envelope,
+    frequency: u16,
+    enabled: bool,
+    next_tick: u64,
+}
+
+impl Square {
+    pub(super) fn new(with_sweep: bool) -> Self {
+        Self {
+            sweep: with_sweep.then(Sweep::new),
+            duty: 0,
+            duty_position: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            frequency: 0,
+            enabled: false,
+            next_tick: u64::MAX,
+        }
+    }
+
+    pub(super) fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Self::new(self.sweep.is_some());
+        self.length = length;
+    }
+
+    fn period(&self) -> u64 {
+        (2048 - self.frequency as u64) * 4
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_position)) & 1;
+
+        high * self.envelope.volume()
+    }
+
+    pub(super) fn length(&self) -> &Length {
+        &self.length
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.next_tick = u64::MAX;
+    }
+
+    pub(super) fn next_tick(&self) -> u64 {
+        self.next_tick
+    }
+
+    pub(super) fn tick(&mut self) {
+        self.duty_position = (self.duty_position + 1) % 8;
+        self.next_tick += self.period();
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.disable();
+        }
+    }
+
+    pub(super) fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        match sweep.clock() {
+            Some(Some(frequency)) => self.frequency = frequency,
+            Some(None) => {}
+            None => self.disable(),
+        }
+    }
+
+    pub(super) fn sweep_settled(&self) -> bool {
+        self.sweep.as_ref().is_none_or(Sweep::settled)
+    }
+
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => match &self.sweep {
+                Some(sweep) => 0b1000_0000 | sweep.read(),
+                None => 0xff,
+            },
+            1 => self.duty << 6 | 0b0011_1111,
+            2 => self.envelope.read(),
+            3 => 0xff,
+            4 => (self.length.enable() as u8) << 6 | 0b1011_1111,
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0b0011_1111);
+    }
+
+    /// Set a register without disabling the channel, clocking the length counter
+    /// or triggering the channel
+    pub(super) fn poke(&mut self, cycle: u64, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.write(val);
+                }
+            }
+            2 => self.envelope.write(val),
+            4 => {
+                self.frequency = (self.frequency & 0x00ff) | ((val as u16) & 0b111) << 8;
+                self.length.set_enable((val & 0b0100_0000) != 0);
+            }
+            _ => self.write(cycle, reg, val, false),
+        }
+    }
+
+    pub(super) fn write(&mut self, cycle: u64, reg: u16, val: u8, extra_length_clock: bool) {
+        match reg {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    if sweep.write(val) {
+                        self.disable();
+                    }
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.write_length(val);
+            }
+            2 => {
+                self.envelope.write(val);
+
+                if !self.envelope.dac_enabled() {
+                    self.disable();
+                }
+            }
+            3 => {
+                self.frequency = (self.frequency & 0x0700) | (val as u16);
+            }
+            4 => {
+                self.frequency = (self.frequency & 0x00ff) | ((val as u16) & 0b111) << 8;
+
+                let trigger = (val & 0b1000_0000) != 0;
+                let length_enable = (val & 0b0100_0000) != 0;
+
+                if self
+                    .length
+                    .write_control(length_enable, trigger, extra_length_clock)
+                {
+                    self.disable();
+                }
+
+                if trigger {
+                    self.trigger(cycle);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self, cycle: u64) {
+        self.enabled = true;
+        self.next_tick = cycle + self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            if sweep.trigger(self.frequency).is_none() {
+                self.disable();
+            }
+        }
+
+        if !self.envelope.dac_enabled() {
+            self.disable();
+        }
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.read());
+        writer.put(&self.timer);
+        writer.put(&self.shadow);
+        writer.put(&self.enabled);
+        writer.put(&self.negate_used);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let reg: u8 = reader.get()?;
+        self.period = (reg >> 4) & 0b0000_0111;
+        self.negate = (reg & 0b0000_1000) != 0;
+        self.shift = reg & 0b0000_0111;
+        self.timer = reader.get()?;
+        self.shadow = reader.get()?;
+        self.enabled = reader.get()?;
+        self.negate_used = reader.get()?;
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Square {
+    fn save_state(&self, writer: &mut StateWriter) {
+        if let Some(sweep) = &self.sweep {
+            sweep.save_state(writer);
+        }
+
+        writer.put(&self.duty);
+        writer.put(&self.duty_position);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.put(&self.frequency);
+        writer.put(&self.enabled);
+        writer.put(&self.next_tick);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.load_state(reader)?;
+        }
+
+        self.duty = reader.get::<u8>()? & 0b11;
+        self.duty_position = reader.get::<u8>()? % 8;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.frequency = reader.get::<u16>()? & 0x07ff;
+        self.enabled = reader.get()?;
+        self.next_tick = reader.get()?;
+
+        Ok(())
+    }
+}
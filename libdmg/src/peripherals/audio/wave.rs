@@ -0,0 +1,223 @@
+use super::length::Length;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+pub(super) struct Wave {
+    dac_enable: bool,
+    length: Length,
+    volume_code: u8,
+    frequency: u16,
+    enabled: bool,
+    position: u8,
+    sample: u8,
+    ram: [u8; 16],
+    next_tick: u64,
+}
+
+impl Wave {
+    pub(super) fn new() -> Self {
+        Self {
+            dac_enable: false,
+            length: Length::new(256),
+            volume_code: 0,
+            frequency: 0,
+            enabled: false,
+            position: 0,
+            sample: 0,
+            ram: [0u8; 16],
+            next_tick: u64::MAX,
+        }
+    }
+
+    pub(super) fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(256));
+        let ram = self.ram;
+        *self = Self::new();
+        self.length = length;
+        self.ram = ram;
+    }
+
+    fn period(&self) -> u64 {
+        (2048 - self.frequency as u64) * 2
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.volume_code {
+            0 => 0,
+            1 => self.sample,
+            2 => self.sample >> 1,
+            _ => self.sample >> 2,
+        }
+    }
+
+    pub(super) fn length(&self) -> &Length {
+        &self.length
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.next_tick = u64::MAX;
+    }
+
+    pub(super) fn next_tick(&self) -> u64 {
+        self.next_tick
+    }
+
+    pub(super) fn tick(&mut self) {
+        self.position = (self.position + 1) % 32;
+
+        let byte = self.ram[(self.position / 2) as usize];
+
+        self.sample = match self.position % 2 {
+            0 => byte >> 4,
+            _ => byte & 0x0f,
+        };
+
+        self.next_tick += self.period();
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.disable();
+        }
+    }
+
+    /// While the channel is playing the CPU can only access the
+    /// wave RAM byte that is currently being played.
+    fn ram_offset(&self, cycle: u64, addr: u16) -> usize {
+        if !self.enabled {
+            return (addr & 0x000f) as usize;
+        }
+
+        let ticks = match cycle.checked_sub(self.next_tick) {
+            Some(elapsed) => 1 + elapsed / self.period(),
+            None => 0,
+        };
+
+        let position = (self.position as u64 + ticks) % 32;
+
+        (position / 2) as usize
+    }
+
+    pub(super) fn read_ram(&self, cycle: u64, addr: u16) -> u8 {
+        self.ram[self.ram_offset(cycle, addr)]
+    }
+
+    pub(super) fn write_ram(&mut self, cycle: u64, addr: u16, val: u8) {
+        self.ram[self.ram_offset(cycle, addr)] = val;
+    }
+
+    pub(super) fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => (self.dac_enable as u8) << 7 | 0b0111_1111,
+            1 => 0xff,
+            2 => self.volume_code << 5 | 0b1001_1111,
+            3 => 0xff,
+            4 => (self.length.enable() as u8) << 6 | 0b1011_1111,
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+
+    /// Set a register without disabling the channel, clocking the length counter
+    /// or triggering the channel
+    pub(super) fn poke(&mut self, cycle: u64, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.dac_enable = (val & 0b1000_0000) != 0;
+            }
+            4 => {
+                self.frequency = (self.frequency & 0x00ff) | ((val as u16) & 0b111) << 8;
+                self.length.set_enable((val & 0b0100_0000) != 0);
+            }
+            _ => self.write(cycle, reg, val, false),
+        }
+    }
+
+    pub(super) fn write(&mut self, cycle: u64, reg: u16, val: u8, extra_length_clock: bool) {
+        match reg {
+            0 => {
+                self.dac_enable = (val & 0b1000_0000) != 0;
+
+                if !self.dac_enable {
+                    self.disable();
+                }
+            }
+            1 => self.write_length(val),
+            2 => {
+                self.volume_code = (val >> 5) & 0b0000_0011;
+            }
+            3 => {
+                self.frequency = (self.frequency & 0x0700) | (val as u16);
+            }
+            4 => {
+                self.frequency = (self.frequency & 0x00ff) | ((val as u16) & 0b111) << 8;
+
+                let trigger = (val & 0b1000_0000) != 0;
+                let length_enable = (val & 0b0100_0000) != 0;
+
+                if self
+                    .length
+                    .write_control(length_enable, trigger, extra_length_clock)
+                {
+                    self.disable();
+                }
+
+                if trigger {
+                    self.trigger(cycle);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self, cycle: u64) {
+        self.enabled = self.dac_enable;
+        self.position = 0;
+
+        // The first sample is only fetched after a short delay
+        self.next_tick = match self.enabled {
+            true => cycle + self.period() + 6,
+            false => u64::MAX,
+        };
+    }
+}
+
+impl Snapshot for Wave {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.dac_enable);
+        self.length.save_state(writer);
+        writer.put(&self.volume_code);
+        writer.put(&self.frequency);
+        writer.put(&self.enabled);
+        writer.put(&self.position);
+        writer.put(&self.sample);
+        writer.put(&self.ram);
+        writer.put(&self.next_tick);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.dac_enable = reader.get()?;
+        self.length.load_state(reader)?;
+        self.volume_code = reader.get::<u8>()? & 0b11;
+        self.frequency = reader.get::<u16>()? & 0x07ff;
+        self.enabled = reader.get()?;
+        self.position = reader.get::<u8>()? % 32;
+        self.sample = reader.get::<u8>()? & 0x0f;
+        self.ram = reader.get()?;
+        self.next_tick = reader.get()?;
+
+        Ok(())
+    }
+}
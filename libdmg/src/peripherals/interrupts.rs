@@ -1,6 +1,6 @@
 use std::ops::{BitAnd, BitOr};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Interrupt {
     VBlank,
     Lcd,
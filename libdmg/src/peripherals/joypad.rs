@@ -1,4 +1,5 @@
 use super::{Interrupt, InterruptMask, InterruptSource};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 #[derive(Clone, Copy)]
 pub enum Button {
@@ -48,6 +49,7 @@ impl From<&[Button]> for Buttons {
     }
 }
 
+#[derive(Clone)]
 pub struct Joypad {
     buttons: Buttons,
     select_buttons: bool,
@@ -101,3 +103,21 @@ impl InterruptSource for Joypad {
         u64::MAX
     }
 }
+
+impl Snapshot for Joypad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.buttons.0);
+        writer.put(&self.select_buttons);
+        writer.put(&self.select_dpad);
+        writer.put(&self.irq_pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.buttons = Buttons(reader.get()?);
+        self.select_buttons = reader.get()?;
+        self.select_dpad = reader.get()?;
+        self.irq_pending = reader.get()?;
+
+        Ok(())
+    }
+}
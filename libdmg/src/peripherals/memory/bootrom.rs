@@ -1,20 +1,28 @@
 use std::sync::Arc;
 
+use crate::Error;
+
 #[derive(Clone)]
 pub struct BootRom {
     rom: Arc<[u8]>,
 }
 
 impl BootRom {
-    pub(crate) fn new(rom: Vec<u8>) -> Self {
-        assert!(rom.len() == 256);
+    pub(crate) fn new(rom: Vec<u8>) -> Result<Self, Error> {
+        if rom.len() != 256 {
+            return Err(Error::BootRomSize(rom.len()));
+        }
 
         let rom = rom.into_boxed_slice().into();
 
-        Self { rom }
+        Ok(Self { rom })
     }
 
     pub(crate) fn read(&self, addr: u16) -> u8 {
         self.rom[addr as usize]
     }
+
+    pub(crate) fn poke(&mut self, addr: u16, val: u8) {
+        Arc::make_mut(&mut self.rom)[addr as usize] = val;
+    }
 }
@@ -1,92 +1,273 @@
 use std::sync::Arc;
 
-use log::{error, info};
+use crate::state::{self, Snapshot, StateError, StateReader, StateWriter};
+use crate::Error;
+
+mod header;
+mod mbc;
+
+use header::HEADER_END;
+pub use header::{CartridgeInfo, CartridgeType, CgbSupport, Licensee};
+pub use mbc::RtcClock;
+use mbc::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
 
 #[derive(Clone)]
 pub struct Cartridge {
+    info: Arc<CartridgeInfo>,
     rom: Arc<[u8]>,
+    rom_hash: u64,
     ram: Vec<u8>,
-    rom_bank: u8,
-    ram_bank: u8,
-    ram_write_enable: bool,
+    mbc: Box<dyn Mbc>,
+    generation: u64,
+    saved_generation: u64,
+    save_requested: bool,
 }
 
 impl Cartridge {
-    pub fn new(rom: Vec<u8>, ram: Option<Vec<u8>>) -> Self {
+    /// Create a cartridge from a ROM image and optionally the content of a .sav file.
+    /// If the save file contains an RTC trailer it is used to restore the clock,
+    /// a save file shorter than the cartridge RAM is padded with zeros.
+    /// Fails if the ROM is too short to contain a cartridge header
+    /// or if the save file holds more than the RAM content and a valid RTC trailer.
+    pub fn new(rom: Vec<u8>, ram: Option<Vec<u8>>) -> Result<Self, Error> {
+        if rom.len() < HEADER_END {
+            return Err(Error::RomTooSmall(rom.len()));
+        }
+
+        let info = Arc::new(CartridgeInfo::new(&rom));
+        let mut mbc = mbc::new(&info, &rom);
+        let rom_hash = state::rom_hash(&rom);
         let rom = rom.into_boxed_slice().into();
-        let ram = ram.unwrap_or_else(|| vec![0u8; 4 * 8192]);
 
-        let rom_bank = 1;
-        let ram_bank = 0;
-        let ram_write_enable = false;
+        let ram_size = mbc::ram_size(&info);
+        let mut ram = ram.unwrap_or_default();
+
+        let trailer_size = ram.len().saturating_sub(ram_size);
+
+        if trailer_size != 0 {
+            let rtc = mbc
+                .rtc_mut()
+                .filter(|_| {
+                    trailer_size == mbc::TRAILER_SIZE || trailer_size == mbc::TRAILER_SIZE_SHORT
+                })
+                .ok_or(Error::SaveTooLarge(ram.len()))?;
 
-        Self {
+            rtc.load_trailer(&ram[ram_size..]);
+        }
+
+        ram.resize(ram_size, 0);
+
+        Ok(Self {
+            info,
             rom,
+            rom_hash,
             ram,
-            rom_bank,
-            ram_bank,
-            ram_write_enable,
+            mbc,
+            generation: 0,
+            saved_generation: 0,
+            save_requested: false,
+        })
+    }
+
+    pub fn info(&self) -> &CartridgeInfo {
+        &self.info
+    }
+
+    /// Select the time source of the real-time clock (if the cartridge has one)
+    /// before the cartridge is used to create a `Dmg`. Use `Dmg::set_rtc_clock` afterwards.
+    /// The time restored from a save file is kept.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.set_rtc_clock_at(0, clock);
+    }
+
+    pub(crate) fn set_rtc_clock_at(&mut self, cycle: u64, clock: RtcClock) {
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            rtc.set_clock(cycle, clock);
         }
     }
 
-    pub(crate) fn read(&self, addr: u16) -> u8 {
-        match addr {
-            0x0000..=0x3fff => {
-                let offset = addr as usize;
-                self.rom[offset]
+    /// Whether the cartridge has a battery that keeps RAM content (and time)
+    /// when switched off, meaning there is something worth saving.
+    pub fn is_persistent(&self) -> bool {
+        self.info.cartridge_type.has_battery()
+    }
+
+    pub(crate) fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub(crate) fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Incremented on every write to cartridge RAM or RTC registers
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(crate) fn take_save_request(&mut self) -> bool {
+        std::mem::take(&mut self.save_requested)
+    }
+
+    /// The content of the cartridge RAM in the common .sav file format,
+    /// including the RTC trailer for cartridges with a real-time clock.
+    pub(crate) fn save_data(&mut self, cycle: u64) -> Option<Vec<u8>> {
+        if !self.is_persistent() {
+            return None;
+        }
+
+        let mut data = self.ram.clone();
+
+        if let Some(rtc) = self.mbc.rtc() {
+            data.extend_from_slice(&rtc.save_trailer(cycle));
+        }
+
+        self.saved_generation = self.generation;
+
+        Some(data)
+    }
+
+    pub(crate) fn rom_banks(&self) -> usize {
+        self.rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    pub(crate) fn ram_banks(&self) -> usize {
+        self.ram.len().div_ceil(RAM_BANK_SIZE)
+    }
+
+    /// The ROM bank that is currently mapped at `addr`
+    pub(crate) fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc.rom_bank(addr) % self.rom_banks().max(1)
+    }
+
+    pub(crate) fn rom_bank_data(&self, bank: usize) -> Option<&[u8]> {
+        self.rom.chunks(ROM_BANK_SIZE).nth(bank)
+    }
+
+    /// Read `addr` (0x0000-0x3fff or 0x4000-0x7fff) in ROM `bank`
+    pub(crate) fn peek_rom(&self, bank: usize, addr: u16) -> Option<u8> {
+        let offset = bank * ROM_BANK_SIZE + (addr as usize) % ROM_BANK_SIZE;
+
+        self.rom.get(offset).copied()
+    }
+
+    /// Patch `addr` (0x0000-0x3fff or 0x4000-0x7fff) in ROM `bank`.
+    /// Returns false if there is no such address in the ROM.
+    pub(crate) fn poke_rom(&mut self, bank: usize, addr: u16, val: u8) -> bool {
+        let offset = bank * ROM_BANK_SIZE + (addr as usize) % ROM_BANK_SIZE;
+
+        match Arc::make_mut(&mut self.rom).get_mut(offset) {
+            Some(dst) => {
+                *dst = val;
+                true
             }
-            0x4000..=0x7fff => {
-                let offset = (addr as usize) - 0x4000;
-                let bank_base = (self.rom_bank as usize) * 16384;
-                self.rom[bank_base + offset]
+            None => false,
+        }
+    }
+
+    /// Read `addr` (0x0000-0x1fff or 0xa000-0xbfff) in RAM `bank`
+    pub(crate) fn peek_ram(&self, bank: usize, addr: u16) -> Option<u8> {
+        let offset = bank * RAM_BANK_SIZE + (addr as usize) % RAM_BANK_SIZE;
+
+        self.ram.get(offset).copied()
+    }
+
+    /// Write `addr` (0x0000-0x1fff or 0xa000-0xbfff) in RAM `bank`,
+    /// no matter if RAM access is enabled.
+    /// Returns false if there is no such address in the RAM.
+    pub(crate) fn poke_ram(&mut self, bank: usize, addr: u16, val: u8) -> bool {
+        let offset = bank * RAM_BANK_SIZE + (addr as usize) % RAM_BANK_SIZE;
+
+        match self.ram.get_mut(offset) {
+            Some(dst) => {
+                *dst = val;
+                self.generation += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Write to the ROM or RAM bank that is currently mapped at `addr`
+    /// without going through the memory bank controller.
+    /// RTC registers can not be written this way.
+    pub(crate) fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7fff => {
+                self.poke_rom(self.rom_bank(addr), addr, val);
             }
             0xa000..=0xbfff => {
-                let offset = (addr as usize) - 0xa000;
-                let bank_base = (self.ram_bank as usize) * 8192;
-                self.ram[bank_base + offset]
+                let banks = self.ram_banks().max(1);
+
+                // MBC2 RAM is smaller than a bank and mirrored throughout 0xa000-0xbfff
+                let addr = match self.ram.len() {
+                    0 => addr,
+                    len => addr % (len.min(RAM_BANK_SIZE) as u16),
+                };
+
+                if let Some(bank) = self.mbc.ram_bank() {
+                    self.poke_ram(bank % banks, addr, val);
+                }
             }
             _ => panic!("Address {addr} is not in cartidge space"),
         }
     }
 
-    pub(crate) fn write(&mut self, addr: u16, val: u8) {
+    pub(crate) fn read(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x1fff => {
-                match val {
-                    0x0a => self.ram_write_enable = true,
-                    0x00 => {
-                        // TODO: notify user to save the cartridge ram now
-                        self.ram_write_enable = false;
-                    }
-                    _ => info!(
-                        "Tried to write 0x{val:02x} to 0x{addr:04x} which is not a valid value"
-                    ),
-                }
-            }
-            0x2000..=0x3fff => {
-                let bank = val & 0x7f;
-                self.rom_bank = if bank == 0 { 1 } else { bank };
-            }
-            0x4000..=0x5fff => {
-                if val < 4 {
-                    self.ram_bank = val;
-                } else {
-                    error!("Enabling the RTC registers is not implemented");
+            0x0000..=0x7fff => mbc::rom_read(&self.rom, self.mbc.rom_bank(addr), addr),
+            0xa000..=0xbfff => self.mbc.read_ram(&self.ram, addr),
+            _ => panic!("Address {addr} is not in cartidge space"),
+        }
+    }
+
+    pub(crate) fn write(&mut self, cycle: u64, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7fff => {
+                let enabled_pre = self.mbc.ram_enabled();
+                self.mbc.write_register(cycle, addr, val);
+                let enabled_post = self.mbc.ram_enabled();
+
+                // Games disable RAM access once they are done writing to it,
+                // which makes this a good point in time to persist it.
+                let unsaved = self.generation != self.saved_generation;
+
+                if enabled_pre && !enabled_post && unsaved && self.is_persistent() {
+                    self.save_requested = true;
                 }
             }
-            0x6000..=0x7fff => {
-                error!("Latching RTC data is not implemented");
-            }
             0xa000..=0xbfff => {
-                if self.ram_write_enable {
-                    let offset = (addr as usize) - 0xa000;
-                    let bank_base = (self.ram_bank as usize) * 8192;
-                    self.ram[bank_base + offset] = val;
-                } else {
-                    info!("Tried to write 0x{val:02x} to 0x{addr:04x} while ram write disabled");
+                if self.mbc.ram_enabled() {
+                    self.generation += 1;
                 }
+
+                self.mbc.write_ram(cycle, &mut self.ram, addr, val)
             }
             _ => panic!("Address {addr} is not in cartidge space"),
         }
     }
 }
+
+impl Snapshot for Cartridge {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&(self.ram.len() as u32));
+        writer.put_bytes(&self.ram);
+        self.mbc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let len: u32 = reader.get()?;
+
+        if len as usize != self.ram.len() {
+            return Err(StateError::Invalid("cartridge RAM size"));
+        }
+
+        self.ram.copy_from_slice(reader.get_bytes(len as usize)?);
+        self.mbc.load_state(reader)?;
+
+        // The restored RAM content is unrelated to what was saved to disk before
+        self.generation += 1;
+
+        Ok(())
+    }
+}
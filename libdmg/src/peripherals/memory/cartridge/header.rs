@@ -0,0 +1,258 @@
+pub(super) const HEADER_END: usize = 0x0150;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Supported,
+    Required,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    /// The single byte licensee code at 0x014b
+    Old(u8),
+    /// The two character licensee code at 0x0144, used if the old code is 0x33
+    New(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8),
+}
+
+impl CartridgeType {
+    fn from_byte(val: u8) -> Self {
+        match val {
+            0x00 => Self::RomOnly,
+            0x01 => Self::Mbc1,
+            0x02 => Self::Mbc1Ram,
+            0x03 => Self::Mbc1RamBattery,
+            0x05 => Self::Mbc2,
+            0x06 => Self::Mbc2Battery,
+            0x08 => Self::RomRam,
+            0x09 => Self::RomRamBattery,
+            0x0b => Self::Mmm01,
+            0x0c => Self::Mmm01Ram,
+            0x0d => Self::Mmm01RamBattery,
+            0x0f => Self::Mbc3TimerBattery,
+            0x10 => Self::Mbc3TimerRamBattery,
+            0x11 => Self::Mbc3,
+            0x12 => Self::Mbc3Ram,
+            0x13 => Self::Mbc3RamBattery,
+            0x19 => Self::Mbc5,
+            0x1a => Self::Mbc5Ram,
+            0x1b => Self::Mbc5RamBattery,
+            0x1c => Self::Mbc5Rumble,
+            0x1d => Self::Mbc5RumbleRam,
+            0x1e => Self::Mbc5RumbleRamBattery,
+            0x20 => Self::Mbc6,
+            0x22 => Self::Mbc7SensorRumbleRamBattery,
+            0xfc => Self::PocketCamera,
+            0xfd => Self::BandaiTama5,
+            0xfe => Self::HuC3,
+            0xff => Self::HuC1RamBattery,
+            _ => Self::Unknown(val),
+        }
+    }
+
+    pub fn has_ram(self) -> bool {
+        matches!(
+            self,
+            Self::Mbc1Ram
+                | Self::Mbc1RamBattery
+                | Self::Mbc2
+                | Self::Mbc2Battery
+                | Self::RomRam
+                | Self::RomRamBattery
+                | Self::Mmm01Ram
+                | Self::Mmm01RamBattery
+                | Self::Mbc3TimerRamBattery
+                | Self::Mbc3Ram
+                | Self::Mbc3RamBattery
+                | Self::Mbc5Ram
+                | Self::Mbc5RamBattery
+                | Self::Mbc5RumbleRam
+                | Self::Mbc5RumbleRamBattery
+                | Self::Mbc6
+                | Self::Mbc7SensorRumbleRamBattery
+                | Self::PocketCamera
+                | Self::HuC3
+                | Self::HuC1RamBattery
+        )
+    }
+
+    pub fn has_battery(self) -> bool {
+        matches!(
+            self,
+            Self::Mbc1RamBattery
+                | Self::Mbc2Battery
+                | Self::RomRamBattery
+                | Self::Mmm01RamBattery
+                | Self::Mbc3TimerBattery
+                | Self::Mbc3TimerRamBattery
+                | Self::Mbc3RamBattery
+                | Self::Mbc5RamBattery
+                | Self::Mbc5RumbleRamBattery
+                | Self::Mbc7SensorRumbleRamBattery
+                | Self::HuC3
+                | Self::HuC1RamBattery
+        )
+    }
+
+    pub fn has_timer(self) -> bool {
+        matches!(
+            self,
+            Self::Mbc3TimerBattery | Self::Mbc3TimerRamBattery | Self::HuC3
+        )
+    }
+
+    pub fn has_rumble(self) -> bool {
+        matches!(
+            self,
+            Self::Mbc5Rumble
+                | Self::Mbc5RumbleRam
+                | Self::Mbc5RumbleRamBattery
+                | Self::Mbc7SensorRumbleRamBattery
+        )
+    }
+}
+
+/// Information parsed from the cartridge header at 0x0100-0x014f
+#[derive(Debug, Clone)]
+pub struct CartridgeInfo {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    /// ROM size in bytes
+    pub rom_size: usize,
+    /// External RAM size in bytes as declared by the header
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| match b {
+            0x20..=0x7e => *b as char,
+            _ => '?',
+        })
+        .collect()
+}
+
+impl CartridgeInfo {
+    /// Parse the header of `rom`.
+    /// Header bytes missing from a truncated ROM are treated as zero,
+    /// which results in failing checksums.
+    pub fn new(rom: &[u8]) -> Self {
+        let mut header = [0u8; HEADER_END];
+        let available = rom.len().min(HEADER_END);
+        header[..available].copy_from_slice(&rom[..available]);
+
+        let cgb = match header[0x0143] {
+            0xc0 => CgbSupport::Required,
+            0x80 => CgbSupport::Supported,
+            _ => CgbSupport::None,
+        };
+
+        // CGB era cartridges shortened the title field to make room for the
+        // CGB flag and (sometimes) a four character manufacturer code.
+        let (title, manufacturer) = match cgb {
+            CgbSupport::None => (ascii(&header[0x0134..0x0144]), None),
+            _ => {
+                let manufacturer = &header[0x013f..0x0143];
+                let is_code = manufacturer.iter().all(|b| b.is_ascii_alphanumeric());
+
+                match is_code {
+                    true => (ascii(&header[0x0134..0x013f]), Some(ascii(manufacturer))),
+                    false => (ascii(&header[0x0134..0x0143]), None),
+                }
+            }
+        };
+
+        let rom_size = match header[0x0148] {
+            n @ 0x00..=0x08 => (32 * 1024) << n,
+            0x52 => 72 * 16 * 1024,
+            0x53 => 80 * 16 * 1024,
+            0x54 => 96 * 16 * 1024,
+            _ => rom.len(),
+        };
+
+        let ram_size = match header[0x0149] {
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            _ => 0,
+        };
+
+        let licensee = match header[0x014b] {
+            0x33 => Licensee::New(ascii(&header[0x0144..0x0146])),
+            code => Licensee::Old(code),
+        };
+
+        let header_checksum = header[0x014d];
+        let header_checksum_computed = header[0x0134..0x014d]
+            .iter()
+            .fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1));
+
+        let global_checksum = u16::from_be_bytes([header[0x014e], header[0x014f]]);
+        let global_checksum_computed = rom
+            .iter()
+            .enumerate()
+            .filter(|(addr, _)| *addr != 0x014e && *addr != 0x014f)
+            .fold(0u16, |acc, (_, b)| acc.wrapping_add(*b as u16));
+
+        Self {
+            title,
+            manufacturer,
+            cgb,
+            sgb: header[0x0146] == 0x03,
+            cartridge_type: CartridgeType::from_byte(header[0x0147]),
+            rom_size,
+            ram_size,
+            licensee,
+            version: header[0x014c],
+            header_checksum,
+            header_checksum_valid: header_checksum == header_checksum_computed,
+            global_checksum,
+            global_checksum_valid: global_checksum == global_checksum_computed,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use log::warn;
+
+use super::{CartridgeInfo, CartridgeType};
+use crate::state::Snapshot;
+
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rom_only;
+mod rtc;
+
+pub use rtc::RtcClock;
+pub(crate) use rtc::{Rtc, TRAILER_SIZE, TRAILER_SIZE_SHORT};
+
+pub(super) const ROM_BANK_SIZE: usize = 16 * 1024;
+pub(super) const RAM_BANK_SIZE: usize = 8 * 1024;
+
+/// A memory bank controller maps the 0x0000-0x7fff and 0xa000-0xbfff
+/// address ranges onto the cartridge ROM and RAM.
+pub(crate) trait Mbc: Send + Snapshot {
+    /// The ROM bank that is mapped at `addr` in 0x0000-0x7fff
+    fn rom_bank(&self, addr: u16) -> usize;
+    /// Write to the control registers at 0x0000-0x7fff
+    fn write_register(&mut self, cycle: u64, addr: u16, val: u8);
+    /// Read from 0xa000-0xbfff
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8;
+    /// Write to 0xa000-0xbfff
+    fn write_ram(&mut self, cycle: u64, ram: &mut [u8], addr: u16, val: u8);
+    /// The RAM bank that is mapped at 0xa000-0xbfff,
+    /// or `None` if something else (like an RTC register) is mapped there
+    fn ram_bank(&self) -> Option<usize>;
+    /// Whether accesses to 0xa000-0xbfff currently reach the RAM (or RTC)
+    fn ram_enabled(&self) -> bool;
+    fn box_clone(&self) -> Box<dyn Mbc>;
+
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+}
+
+impl Clone for Box<dyn Mbc> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Read from the 16KiB ROM `bank`, wrapping around at the end of the ROM
+/// like the unconnected upper address lines on a real cartridge do.
+pub(super) fn rom_read(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    let offset = bank * ROM_BANK_SIZE + (addr as usize) % ROM_BANK_SIZE;
+
+    match rom.len() {
+        0 => 0xff,
+        len => rom[offset % len],
+    }
+}
+
+fn ram_offset(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    let offset = bank * RAM_BANK_SIZE + (addr as usize) % RAM_BANK_SIZE;
+
+    match ram.len() {
+        0 => None,
+        len => Some(offset % len),
+    }
+}
+
+fn ram_read(ram: &[u8], bank: usize, addr: u16) -> u8 {
+    match ram_offset(ram, bank, addr) {
+        Some(offset) => ram[offset],
+        None => 0xff,
+    }
+}
+
+fn ram_write(ram: &mut [u8], bank: usize, addr: u16, val: u8) {
+    if let Some(offset) = ram_offset(ram, bank, addr) {
+        ram[offset] = val;
+    }
+}
+
+/// The RAM size the cartridge actually needs, which differs from
+/// the header for controllers with built-in RAM.
+pub(super) fn ram_size(info: &CartridgeInfo) -> usize {
+    match info.cartridge_type {
+        CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => mbc2::RAM_SIZE,
+        _ => info.ram_size,
+    }
+}
+
+pub(super) fn new(info: &CartridgeInfo, rom: &[u8]) -> Box<dyn Mbc> {
+    match info.cartridge_type {
+        CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
+            Box::new(rom_only::RomOnly::new())
+        }
+        CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
+            Box::new(mbc1::Mbc1::new(rom))
+        }
+        CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => Box::new(mbc2::Mbc2::new()),
+        CartridgeType::Mbc3
+        | CartridgeType::Mbc3Ram
+        | CartridgeType::Mbc3RamBattery
+        | CartridgeType::Mbc3TimerBattery
+        | CartridgeType::Mbc3TimerRamBattery => {
+            Box::new(mbc3::Mbc3::new(info.cartridge_type.has_timer()))
+        }
+        CartridgeType::Mbc5
+        | CartridgeType::Mbc5Ram
+        | CartridgeType::Mbc5RamBattery
+        | CartridgeType::Mbc5Rumble
+        | CartridgeType::Mbc5RumbleRam
+        | CartridgeType::Mbc5RumbleRamBattery => {
+            Box::new(mbc5::Mbc5::new(info.cartridge_type.has_rumble()))
+        }
+        other => {
+            warn!("Cartridge type {other:?} is not supported, treating it as ROM only");
+            Box::new(rom_only::RomOnly::new())
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use log::info;
+
+use super::{ram_read, ram_write, Mbc, ROM_BANK_SIZE};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+const MULTICART_SIZE: usize = 1024 * 1024;
+const LOGO: std::ops::Range<usize> = 0x0104..0x0134;
+
+#[derive(Clone)]
+pub(super) struct Mbc1 {
+    ram_enable: bool,
+    bank1: u8,
+    bank2: u8,
+    advanced_banking: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub(super) fn new(rom: &[u8]) -> Self {
+        Self {
+            ram_enable: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_banking: false,
+            multicart: is_multicart(rom),
+        }
+    }
+
+    /// Multicarts wire BANK2 to ROM address lines 18-19 instead of 19-20,
+    /// so the upper bit of BANK1 is not connected.
+    fn bank2_shift(&self) -> usize {
+        match self.multicart {
+            true => 4,
+            false => 5,
+        }
+    }
+
+    fn bank1(&self) -> usize {
+        match self.multicart {
+            true => (self.bank1 & 0x0f) as usize,
+            false => self.bank1 as usize,
+        }
+    }
+
+    fn selected_ram_bank(&self) -> usize {
+        match self.advanced_banking {
+            true => self.bank2 as usize,
+            false => 0,
+        }
+    }
+}
+
+/// MBC1 multicarts contain multiple games, each with their own header
+/// and thus another copy of the Nintendo logo in bank 0x10.
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != MULTICART_SIZE {
+        return false;
+    }
+
+    let second_game = 0x10 * ROM_BANK_SIZE;
+    let second_logo = (second_game + LOGO.start)..(second_game + LOGO.end);
+
+    rom[LOGO] == rom[second_logo]
+}
+
+impl Mbc for Mbc1 {
+    fn rom_bank(&self, addr: u16) -> usize {
+        let bank2 = (self.bank2 as usize) << self.bank2_shift();
+
+        match addr {
+            0x0000..=0x3fff if self.advanced_banking => bank2,
+            0x0000..=0x3fff => 0,
+            _ => bank2 | self.bank1(),
+        }
+    }
+
+    fn write_register(&mut self, _cycle: u64, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = (val & 0x0f) == 0x0a;
+            }
+            0x2000..=0x3fff => {
+                // The zero check happens on all five bits, before the bank
+                // number is masked to the ROM size.
+                self.bank1 = match val & 0x1f {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            0x4000..=0x5fff => {
+                self.bank2 = val & 0b11;
+            }
+            _ => {
+                self.advanced_banking = (val & 1) != 0;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xff;
+        }
+
+        ram_read(ram, self.selected_ram_bank(), addr)
+    }
+
+    fn write_ram(&mut self, _cycle: u64, ram: &mut [u8], addr: u16, val: u8) {
+        if !self.ram_enable {
+            info!("Tried to write 0x{val:02x} to 0x{addr:04x} while ram write disabled");
+            return;
+        }
+
+        ram_write(ram, self.selected_ram_bank(), addr, val)
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        Some(self.selected_ram_bank())
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Snapshot for Mbc1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.ram_enable);
+        writer.put(&self.bank1);
+        writer.put(&self.bank2);
+        writer.put(&self.advanced_banking);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enable = reader.get()?;
+        self.bank1 = reader.get::<u8>()? & 0x1f;
+        self.bank2 = reader.get::<u8>()? & 0b11;
+        self.advanced_banking = reader.get()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use log::info;
+
+use super::Mbc;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// The MBC2 contains 512 half-bytes of RAM
+pub(super) const RAM_SIZE: usize = 512;
+
+#[derive(Clone)]
+pub(super) struct Mbc2 {
+    ram_enable: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub(super) fn new() -> Self {
+        Self {
+            ram_enable: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn write_register(&mut self, _cycle: u64, addr: u16, val: u8) {
+        // Bit 8 of the address selects between the two registers
+        match addr {
+            0x0000..=0x3fff if addr & 0x0100 == 0 => {
+                self.ram_enable = (val & 0x0f) == 0x0a;
+            }
+            0x0000..=0x3fff => {
+                self.rom_bank = match val & 0x0f {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xff;
+        }
+
+        // Only the lower nibble is connected, the upper one reads as ones
+        match ram.get((addr as usize) % RAM_SIZE) {
+            Some(val) => 0xf0 | val,
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, _cycle: u64, ram: &mut [u8], addr: u16, val: u8) {
+        if !self.ram_enable {
+            info!("Tried to write 0x{val:02x} to 0x{addr:04x} while ram write disabled");
+            return;
+        }
+
+        if let Some(dst) = ram.get_mut((addr as usize) % RAM_SIZE) {
+            *dst = val & 0x0f;
+        }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Snapshot for Mbc2 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.ram_enable);
+        writer.put(&self.rom_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enable = reader.get()?;
+        self.rom_bank = reader.get::<u8>()? & 0x0f;
+
+        Ok(())
+    }
+}
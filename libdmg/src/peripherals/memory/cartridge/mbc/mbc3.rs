@@ -0,0 +1,127 @@
+use log::info;
+
+use super::{ram_read, ram_write, Mbc, Rtc};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+pub(super) struct Mbc3 {
+    ram_enable: bool,
+    rom_bank: u8,
+    /// Values 0x00-0x07 select a RAM bank, 0x08-0x0c an RTC register
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub(super) fn new(has_timer: bool) -> Self {
+        Self {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: has_timer.then(Rtc::new),
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn write_register(&mut self, cycle: u64, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = (val & 0x0f) == 0x0a;
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank = match val & 0x7f {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank = val;
+            }
+            _ => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.latch(cycle, val);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xff;
+        }
+
+        match (self.ram_bank, &self.rtc) {
+            (0x00..=0x07, _) => ram_read(ram, self.ram_bank as usize, addr),
+            (0x08..=0x0c, Some(rtc)) => rtc.read(self.ram_bank),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, cycle: u64, ram: &mut [u8], addr: u16, val: u8) {
+        if !self.ram_enable {
+            info!("Tried to write 0x{val:02x} to 0x{addr:04x} while ram write disabled");
+            return;
+        }
+
+        match (self.ram_bank, &mut self.rtc) {
+            (0x00..=0x07, _) => ram_write(ram, self.ram_bank as usize, addr, val),
+            (0x08..=0x0c, Some(rtc)) => rtc.write(cycle, self.ram_bank, val),
+            _ => {}
+        }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        match self.ram_bank {
+            0x00..=0x07 => Some(self.ram_bank as usize),
+            _ => None,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+impl Snapshot for Mbc3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.ram_enable);
+        writer.put(&self.rom_bank);
+        writer.put(&self.ram_bank);
+
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enable = reader.get()?;
+        self.rom_bank = reader.get::<u8>()? & 0x7f;
+        self.ram_bank = reader.get()?;
+
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_state(reader)?;
+        }
+
+        Ok(())
+    }
+}
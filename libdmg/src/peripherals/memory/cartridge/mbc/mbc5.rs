@@ -0,0 +1,97 @@
+use log::info;
+
+use super::{ram_read, ram_write, Mbc};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+pub(super) struct Mbc5 {
+    ram_enable: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+}
+
+impl Mbc5 {
+    pub(super) fn new(has_rumble: bool) -> Self {
+        Self {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn write_register(&mut self, _cycle: u64, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = (val & 0x0f) == 0x0a;
+            }
+            0x2000..=0x2fff => {
+                self.rom_bank = (self.rom_bank & 0x100) | (val as u16);
+            }
+            0x3000..=0x3fff => {
+                self.rom_bank = (self.rom_bank & 0x0ff) | ((val as u16) & 1) << 8;
+            }
+            0x4000..=0x5fff => {
+                // On rumble cartridges bit 3 drives the motor instead of RAM banking
+                self.ram_bank = match self.has_rumble {
+                    true => val & 0x07,
+                    false => val & 0x0f,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        match self.ram_enable {
+            true => ram_read(ram, self.ram_bank as usize, addr),
+            false => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, _cycle: u64, ram: &mut [u8], addr: u16, val: u8) {
+        if self.ram_enable {
+            ram_write(ram, self.ram_bank as usize, addr, val)
+        } else {
+            info!("Tried to write 0x{val:02x} to 0x{addr:04x} while ram write disabled");
+        }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        Some(self.ram_bank as usize)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Snapshot for Mbc5 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.ram_enable);
+        writer.put(&self.rom_bank);
+        writer.put(&self.ram_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enable = reader.get()?;
+        self.rom_bank = reader.get::<u16>()? & 0x1ff;
+        self.ram_bank = reader.get::<u8>()? & 0x0f;
+
+        Ok(())
+    }
+}
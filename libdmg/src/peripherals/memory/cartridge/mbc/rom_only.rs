@@ -0,0 +1,47 @@
+use super::{ram_read, ram_write, Mbc};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+pub(super) struct RomOnly;
+
+impl RomOnly {
+    pub(super) fn new() -> Self {
+        Self
+    }
+}
+
+impl Mbc for RomOnly {
+    fn rom_bank(&self, addr: u16) -> usize {
+        (addr as usize) >> 14
+    }
+
+    fn write_register(&mut self, _cycle: u64, _addr: u16, _val: u8) {}
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        ram_read(ram, 0, addr)
+    }
+
+    fn write_ram(&mut self, _cycle: u64, ram: &mut [u8], addr: u16, val: u8) {
+        ram_write(ram, 0, addr, val)
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        true
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Snapshot for RomOnly {
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
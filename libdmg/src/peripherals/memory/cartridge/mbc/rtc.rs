@@ -0,0 +1,312 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+const CYCLES_PER_SECOND: u64 = 4194304;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Size of the RTC state appended to .sav files by other emulators.
+/// Some older emulators use a 44 byte variant with a 32 bit timestamp.
+pub(crate) const TRAILER_SIZE: usize = 48;
+pub(crate) const TRAILER_SIZE_SHORT: usize = 44;
+
+/// The time source driving the real-time clock of MBC3 cartridges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcClock {
+    /// Follow the host system clock, even while the emulator is not running
+    WallClock,
+    /// Derive the time from the emulated cycle counter, for deterministic runs
+    Cycles,
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[derive(Clone)]
+pub(crate) struct Rtc {
+    clock: RtcClock,
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    day_carry: bool,
+    /// Point in time (in clock ticks) at which the registers above were valid
+    base: u64,
+    latched: [u8; 5],
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub(crate) fn new() -> Self {
+        let mut rtc = Self {
+            clock: RtcClock::WallClock,
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            day_carry: false,
+            base: 0,
+            latched: [0; 5],
+            latch_armed: false,
+        };
+
+        rtc.base = rtc.ticks(0);
+
+        rtc
+    }
+
+    /// Switch the time source at `cycle`. The time that passed under the old
+    /// clock is kept, the new one continues counting from there.
+    pub(crate) fn set_clock(&mut self, cycle: u64, clock: RtcClock) {
+        self.update(cycle);
+
+        // Carry the started second over to the new clock
+        let old_tps = self.ticks_per_second() as u128;
+        let fraction = self.ticks(cycle).saturating_sub(self.base) as u128;
+
+        self.clock = clock;
+
+        let fraction = fraction * (self.ticks_per_second() as u128) / old_tps;
+        self.base = self.ticks(cycle).saturating_sub(fraction as u64);
+    }
+
+    fn ticks(&self, cycle: u64) -> u64 {
+        match self.clock {
+            RtcClock::WallClock => unix_nanos(),
+            RtcClock::Cycles => cycle,
+        }
+    }
+
+    fn ticks_per_second(&self) -> u64 {
+        match self.clock {
+            RtcClock::WallClock => NANOS_PER_SECOND,
+            RtcClock::Cycles => CYCLES_PER_SECOND,
+        }
+    }
+
+    fn update(&mut self, cycle: u64) {
+        let now = self.ticks(cycle);
+
+        if self.halt {
+            self.base = now;
+            return;
+        }
+
+        let tps = self.ticks_per_second();
+        let elapsed = now.saturating_sub(self.base) / tps;
+
+        self.base += elapsed * tps;
+        self.add_seconds(elapsed);
+    }
+
+    fn add_seconds(&mut self, seconds: u64) {
+        let total = (self.seconds as u64)
+            + (self.minutes as u64) * 60
+            + (self.hours as u64) * 60 * 60
+            + (self.days as u64) * 24 * 60 * 60
+            + seconds;
+
+        let days = total / (24 * 60 * 60);
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / (60 * 60) % 24) as u8;
+        self.days = (days % 512) as u16;
+
+        if days >= 512 {
+            self.day_carry = true;
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        let day_high =
+            ((self.days >> 8) as u8 & 1) | (self.halt as u8) << 6 | (self.day_carry as u8) << 7;
+
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            day_high,
+        ]
+    }
+
+    /// Read the latched copy of the RTC register `reg` (0x08-0x0c)
+    pub(crate) fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08..=0x0c => self.latched[(reg - 0x08) as usize],
+            _ => 0xff,
+        }
+    }
+
+    pub(crate) fn write(&mut self, cycle: u64, reg: u8, val: u8) {
+        self.update(cycle);
+
+        match reg {
+            0x08 => {
+                // Writing the seconds also resets the sub-second divider
+                self.seconds = val & 0x3f;
+                self.base = self.ticks(cycle);
+            }
+            0x09 => {
+                self.minutes = val & 0x3f;
+            }
+            0x0a => {
+                self.hours = val & 0x1f;
+            }
+            0x0b => {
+                self.days = (self.days & 0x100) | (val as u16);
+            }
+            0x0c => {
+                self.days = (self.days & 0x0ff) | ((val as u16) & 1) << 8;
+                self.halt = (val & 0b0100_0000) != 0;
+                self.day_carry = (val & 0b1000_0000) != 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Writing 0x00 followed by 0x01 copies the current time into the
+    /// latched registers, which are what the CPU actually reads.
+    pub(crate) fn latch(&mut self, cycle: u64, val: u8) {
+        if self.latch_armed && val == 0x01 {
+            self.update(cycle);
+            self.latched = self.registers();
+        }
+
+        self.latch_armed = val == 0x00;
+    }
+
+    pub(crate) fn save_trailer(&self, cycle: u64) -> [u8; TRAILER_SIZE] {
+        let mut rtc = self.clone();
+        rtc.update(cycle);
+
+        let mut trailer = [0u8; TRAILER_SIZE];
+
+        let registers = rtc.registers().into_iter().chain(rtc.latched);
+
+        for (dst, reg) in trailer.chunks_exact_mut(4).zip(registers) {
+            dst.copy_from_slice(&(reg as u32).to_le_bytes());
+        }
+
+        let timestamp = unix_nanos() / NANOS_PER_SECOND;
+        trailer[40..48].copy_from_slice(&timestamp.to_le_bytes());
+
+        trailer
+    }
+
+    pub(crate) fn load_trailer(&mut self, trailer: &[u8]) {
+        let word = |idx: usize| {
+            let bytes = &trailer[idx * 4..idx * 4 + 4];
+            u32::from_le_bytes(bytes.try_into().unwrap()) as u8
+        };
+
+        self.seconds = word(0) & 0x3f;
+        self.minutes = word(1) & 0x3f;
+        self.hours = word(2) & 0x1f;
+        self.days = (word(3) as u16) | ((word(4) as u16) & 1) << 8;
+        self.halt = (word(4) & 0b0100_0000) != 0;
+        self.day_carry = (word(4) & 0b1000_0000) != 0;
+
+        for (idx, latched) in self.latched.iter_mut().enumerate() {
+            *latched = word(5 + idx);
+        }
+
+        let timestamp = match trailer.len() {
+            TRAILER_SIZE => u64::from_le_bytes(trailer[40..48].try_into().unwrap()),
+            _ => u32::from_le_bytes(trailer[40..44].try_into().unwrap()) as u64,
+        };
+
+        // When following the wall clock the time that passed since the save
+        // was written is added the next time the registers are accessed.
+        // Cycle based clocks start counting from the first emulated cycle.
+        self.base = match self.clock {
+            RtcClock::WallClock => timestamp * NANOS_PER_SECOND,
+            RtcClock::Cycles => 0,
+        };
+    }
+}
+
+impl Snapshot for Rtc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.registers());
+        writer.put(&self.base);
+        writer.put(&self.latched);
+        writer.put(&self.latch_armed);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let [seconds, minutes, hours, day_low, day_high] = reader.get()?;
+
+        self.seconds = seconds & 0x3f;
+        self.minutes = minutes & 0x3f;
+        self.hours = hours & 0x1f;
+        self.days = (day_low as u16) | ((day_high as u16) & 1) << 8;
+        self.halt = (day_high & 0b0100_0000) != 0;
+        self.day_carry = (day_high & 0b1000_0000) != 0;
+        self.base = reader.get()?;
+        self.latched = reader.get()?;
+        self.latch_armed = reader.get()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latched_seconds(rtc: &mut Rtc, cycle: u64) -> u64 {
+        rtc.latch(cycle, 0x00);
+        rtc.latch(cycle, 0x01);
+
+        [(0x08, 1), (0x09, 60), (0x0a, 60 * 60), (0x0b, 24 * 60 * 60)]
+            .into_iter()
+            .map(|(reg, scale)| rtc.read(reg) as u64 * scale)
+            .sum()
+    }
+
+    #[test]
+    fn wall_clock_keeps_offline_time_from_trailer() {
+        let mut trailer = [0u8; TRAILER_SIZE];
+        let hour_ago = unix_nanos() / NANOS_PER_SECOND - 60 * 60;
+        trailer[40..48].copy_from_slice(&hour_ago.to_le_bytes());
+
+        let mut rtc = Rtc::new();
+        rtc.load_trailer(&trailer);
+        rtc.set_clock(0, RtcClock::WallClock);
+
+        let seconds = latched_seconds(&mut rtc, 0);
+        assert!((60 * 60..60 * 60 + 5).contains(&seconds), "{seconds}");
+    }
+
+    #[test]
+    fn switching_to_wall_clock_keeps_counted_cycles() {
+        let mut rtc = Rtc::new();
+        rtc.set_clock(0, RtcClock::Cycles);
+
+        let cycle = 100 * CYCLES_PER_SECOND + CYCLES_PER_SECOND / 2;
+        assert_eq!(latched_seconds(&mut rtc, cycle), 100);
+
+        rtc.set_clock(cycle, RtcClock::WallClock);
+        assert_eq!(latched_seconds(&mut rtc, cycle), 100);
+    }
+
+    #[test]
+    fn switching_to_cycles_mid_run_does_not_jump() {
+        let mut rtc = Rtc::new();
+        let cycle = 1000 * CYCLES_PER_SECOND;
+
+        rtc.set_clock(cycle, RtcClock::Cycles);
+        assert!(latched_seconds(&mut rtc, cycle) <= 1);
+
+        let seconds = latched_seconds(&mut rtc, cycle + 5 * CYCLES_PER_SECOND);
+        assert!((5..=6).contains(&seconds), "{seconds}");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 #[derive(Clone)]
 pub struct Ram {
     work_ram: [u8; 8192],
@@ -48,3 +50,17 @@ impl Ram {
         }
     }
 }
+
+impl Snapshot for Ram {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.work_ram);
+        writer.put(&self.high_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.work_ram = reader.get()?;
+        self.high_ram = reader.get()?;
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use log::warn;
 
 use super::{Interrupt, InterruptMask, InterruptSource};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 #[derive(Clone, Copy)]
 enum Clock {
@@ -8,6 +9,7 @@ enum Clock {
     Internal,
 }
 
+#[derive(Clone)]
 pub struct Serial {
     data: u8,
     transfer_enable: bool,
@@ -76,3 +78,25 @@ impl InterruptSource for Serial {
         u64::MAX
     }
 }
+
+impl Snapshot for Serial {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.data);
+        writer.put(&self.transfer_enable);
+        writer.put(&(self.clock_select as u8));
+        writer.put(&self.irq_pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.data = reader.get()?;
+        self.transfer_enable = reader.get()?;
+        self.clock_select = match reader.get::<u8>()? {
+            0 => Clock::External,
+            1 => Clock::Internal,
+            _ => return Err(StateError::Invalid("serial clock")),
+        };
+        self.irq_pending = reader.get()?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
 use super::{Interrupt, InterruptMask, InterruptSource};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// After TIMA overflows it reads as zero for this many cycles
+/// before it is reloaded from TMA and the interrupt is raised.
+const RELOAD_DELAY: u64 = 4;
 
 #[derive(Clone, Copy)]
 enum Clock {
@@ -8,53 +13,188 @@ enum Clock {
     Div256,
 }
 
+impl Clock {
+    /// TIMA is incremented on the falling edge of this bit of the internal counter
+    fn bit(self) -> u32 {
+        match self {
+            Self::Div1024 => 9,
+            Self::Div16 => 3,
+            Self::Div64 => 5,
+            Self::Div256 => 7,
+        }
+    }
+
+    fn period(self) -> u64 {
+        2 << self.bit()
+    }
+}
+
+#[derive(Clone)]
 pub struct Timer {
+    div_base: u64,
+    tima: u8,
+    tima_cycle: u64,
     tma: u8,
     enable: bool,
     clock: Clock,
+    reload_cycle: Option<u64>,
     irq_pending: bool,
 }
 
 impl Timer {
     pub(crate) fn new() -> Self {
         Self {
+            div_base: 0,
+            tima: 0,
+            tima_cycle: 0,
             tma: 0,
             enable: false,
             clock: Clock::Div1024,
+            reload_cycle: None,
             irq_pending: false,
         }
     }
 
+    /// The 16 bit internal counter whose upper byte is visible as DIV
+    pub(crate) fn counter(&self, cycle: u64) -> u16 {
+        cycle.saturating_sub(self.div_base) as u16
+    }
+
+    fn edge_signal(&self, cycle: u64) -> bool {
+        self.enable && (self.counter(cycle) >> self.clock.bit()) & 1 != 0
+    }
+
+    /// The cycle at which TIMA will overflow if nothing intervenes
+    fn next_overflow(&self) -> Option<u64> {
+        if !self.enable || self.reload_cycle.is_some() {
+            return None;
+        }
+
+        let period = self.clock.period();
+        let edges_needed = 256 - (self.tima as u64);
+        let edges_passed = (self.tima_cycle - self.div_base) / period;
+
+        Some(self.div_base + (edges_passed + edges_needed) * period)
+    }
+
+    fn increment(&mut self, cycle: u64) {
+        if self.tima == 0xff {
+            self.tima = 0;
+            self.reload_cycle = Some(cycle + RELOAD_DELAY);
+        } else {
+            self.tima += 1;
+        }
+    }
+
+    fn update(&mut self, cycle: u64) {
+        loop {
+            if let Some(reload) = self.reload_cycle.filter(|r| *r <= cycle) {
+                self.tima = self.tma;
+                self.tima_cycle = reload;
+                self.reload_cycle = None;
+                self.irq_pending = true;
+                continue;
+            }
+
+            if let Some(overflow) = self.next_overflow().filter(|o| *o <= cycle) {
+                self.tima = 0;
+                self.tima_cycle = overflow;
+                self.reload_cycle = Some(overflow + RELOAD_DELAY);
+                continue;
+            }
+
+            break;
+        }
+
+        if self.enable && self.reload_cycle.is_none() {
+            let period = self.clock.period();
+            let edges_before = (self.tima_cycle - self.div_base) / period;
+            let edges_after = (cycle - self.div_base) / period;
+
+            self.tima += (edges_after - edges_before) as u8;
+        }
+
+        self.tima_cycle = cycle;
+    }
+
+    fn updated(&self, cycle: u64) -> Self {
+        let mut timer = self.clone();
+        timer.update(cycle.max(self.tima_cycle));
+        timer
+    }
+
     pub(crate) fn read(&self, cycle: u64, addr: u16) -> u8 {
         match addr {
-            0xff04 => (cycle / 256) as u8,
-            0xff05 => unimplemented!("FF05 — TIMA: Timer counter"),
+            0xff04 => (self.counter(cycle) >> 8) as u8,
+            0xff05 => self.updated(cycle).tima,
             0xff06 => self.tma,
             0xff07 => {
                 let en = self.enable as u8;
                 let clk = self.clock as u8;
-                en << 2 | clk
+                0b1111_1000 | en << 2 | clk
             }
             _ => 0,
         }
     }
 
-    pub(crate) fn write(&mut self, _cycle: u64, addr: u16, val: u8) {
+    fn set_control(&mut self, val: u8) {
+        self.enable = (val & 0b0000_0100) != 0;
+        self.clock = match val & 0b0000_0011 {
+            0 => Clock::Div1024,
+            1 => Clock::Div16,
+            2 => Clock::Div64,
+            3 => Clock::Div256,
+            _ => panic!(),
+        };
+    }
+
+    /// Set a register without the TIMA increment a TAC write can cause
+    pub(crate) fn poke(&mut self, cycle: u64, addr: u16, val: u8) {
+        match addr {
+            0xff07 => {
+                self.update(cycle.max(self.tima_cycle));
+                self.set_control(val);
+            }
+            _ => self.write(cycle, addr, val),
+        }
+    }
+
+    pub(crate) fn write(&mut self, cycle: u64, addr: u16, val: u8) {
+        let cycle = cycle.max(self.tima_cycle);
+
+        self.update(cycle);
+
         match addr {
-            0xff04 => unimplemented!("FF04 — DIV: Divider register"),
-            0xff05 => unimplemented!("FF05 — TIMA: Timer counter"),
+            0xff04 => {
+                // Resetting the counter while the selected bit is high
+                // results in a falling edge, which increments TIMA.
+                let edge = self.edge_signal(cycle);
+
+                self.div_base = cycle;
+
+                if edge {
+                    self.increment(cycle);
+                }
+            }
+            0xff05 => {
+                // Writing TIMA in the cycles between the overflow and
+                // the reload cancels the reload and the interrupt.
+                self.tima = val;
+                self.reload_cycle = None;
+            }
             0xff06 => {
                 self.tma = val;
             }
             0xff07 => {
-                self.enable = (val & 0b0000_0100) != 0;
-                self.clock = match val & 0b0000_0011 {
-                    0 => Clock::Div1024,
-                    1 => Clock::Div16,
-                    2 => Clock::Div64,
-                    3 => Clock::Div256,
-                    _ => panic!(),
-                };
+                let edge_pre = self.edge_signal(cycle);
+
+                self.set_control(val);
+
+                // The edge detector sees the AND of the enable bit and
+                // the selected counter bit, so switching either can tick TIMA.
+                if edge_pre && !self.edge_signal(cycle) {
+                    self.increment(cycle);
+                }
             }
             _ => {}
         }
@@ -62,19 +202,165 @@ impl Timer {
 }
 
 impl InterruptSource for Timer {
-    fn pending(&self, _cycle: u64) -> InterruptMask {
-        if self.irq_pending {
+    fn pending(&self, cycle: u64) -> InterruptMask {
+        if self.irq_pending || self.next_pending(self.tima_cycle) <= cycle {
             Interrupt::Timer.as_mask()
         } else {
             InterruptMask::default()
         }
     }
 
-    fn set_pending(&mut self, _cycle: u64, mask: InterruptMask) {
+    fn set_pending(&mut self, cycle: u64, mask: InterruptMask) {
+        self.update(cycle.max(self.tima_cycle));
         self.irq_pending = mask.is_set(Interrupt::Timer);
     }
 
-    fn next_pending(&self, _cycle: u64) -> u64 {
-        u64::MAX
+    fn next_pending(&self, cycle: u64) -> u64 {
+        let timer = self.updated(cycle);
+
+        match (timer.reload_cycle, timer.next_overflow()) {
+            (Some(reload), _) => reload,
+            (None, Some(overflow)) => overflow + RELOAD_DELAY,
+            (None, None) => u64::MAX,
+        }
+    }
+}
+
+impl Snapshot for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.div_base);
+        writer.put(&self.tima);
+        writer.put(&self.tima_cycle);
+        writer.put(&self.tma);
+        writer.put(&self.enable);
+        writer.put(&(self.clock as u8));
+        writer.put(&self.reload_cycle);
+        writer.put(&self.irq_pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.div_base = reader.get()?;
+        self.tima = reader.get()?;
+        self.tima_cycle = reader.get()?;
+        self.tma = reader.get()?;
+        self.enable = reader.get()?;
+        self.clock = match reader.get::<u8>()? {
+            0 => Clock::Div1024,
+            1 => Clock::Div16,
+            2 => Clock::Div64,
+            3 => Clock::Div256,
+            _ => return Err(StateError::Invalid("timer clock")),
+        };
+        self.reload_cycle = reader.get()?;
+        self.irq_pending = reader.get()?;
+
+        // The counter was reset before TIMA was last updated
+        if self.tima_cycle < self.div_base {
+            return Err(StateError::Invalid("timer cycle"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(timer: &Timer) -> Result<(), StateError> {
+        let mut writer = StateWriter::new(0);
+        timer.save_state(&mut writer);
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data, 0)?;
+        Timer::new().load_state(&mut reader)
+    }
+
+    #[test]
+    fn state_with_tima_updated_before_div_reset_is_rejected() {
+        let mut timer = Timer::new();
+        timer.write(1000, 0xff04, 0);
+        assert_eq!(reload(&timer), Ok(()));
+
+        timer.tima_cycle = 500;
+        assert_eq!(reload(&timer), Err(StateError::Invalid("timer cycle")));
+    }
+
+    /// A timer ticking every 16 cycles, counting from `tima` since cycle 0
+    fn timer(tima: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0, 0xff06, 0x80);
+        timer.write(0, 0xff05, tima);
+        timer.write(0, 0xff07, 0b101);
+        timer
+    }
+
+    fn irq(timer: &Timer, cycle: u64) -> bool {
+        timer.pending(cycle).is_set(Interrupt::Timer)
+    }
+
+    #[test]
+    fn overflow_reloads_from_tma_after_delay() {
+        let timer = timer(0xff);
+
+        assert_eq!(timer.read(15, 0xff05), 0xff);
+        assert_eq!(timer.read(16, 0xff05), 0x00);
+        assert_eq!(timer.read(19, 0xff05), 0x00);
+        assert!(!irq(&timer, 19));
+        assert_eq!(timer.read(20, 0xff05), 0x80);
+        assert!(irq(&timer, 20));
+    }
+
+    #[test]
+    fn tima_write_during_reload_delay_cancels_reload() {
+        let mut timer = timer(0xff);
+        timer.write(18, 0xff05, 0x42);
+
+        assert_eq!(timer.read(20, 0xff05), 0x42);
+        assert!(!irq(&timer, 20));
+        assert!(!irq(&timer, 100));
+    }
+
+    #[test]
+    fn div_write_with_selected_bit_high_increments_tima() {
+        let mut timer = timer(0x00);
+
+        // Bit 3 of the counter is low, no edge
+        timer.write(4, 0xff04, 0);
+        assert_eq!(timer.read(4, 0xff05), 0x00);
+
+        // Bit 3 is high 8 cycles after the reset
+        timer.write(12, 0xff04, 0);
+        assert_eq!(timer.read(12, 0xff05), 0x01);
+
+        // The next edge is a full period after the reset
+        assert_eq!(timer.read(27, 0xff05), 0x01);
+        assert_eq!(timer.read(28, 0xff05), 0x02);
+    }
+
+    #[test]
+    fn tac_write_with_selected_bit_high_increments_tima() {
+        let mut timer = timer(0x00);
+        timer.write(4, 0xff07, 0b101);
+        assert_eq!(timer.read(4, 0xff05), 0x00);
+
+        // Disabling the timer while bit 3 is high
+        timer.write(8, 0xff07, 0b001);
+        assert_eq!(timer.read(8, 0xff05), 0x01);
+
+        // Switching from bit 3 (high) to bit 9 (low)
+        timer.write(24, 0xff07, 0b101);
+        timer.write(24, 0xff07, 0b100);
+        assert_eq!(timer.read(24, 0xff05), 0x02);
+    }
+
+    #[test]
+    fn next_pending_is_the_reload_cycle() {
+        let mut timer = timer(0xfe);
+        assert_eq!(timer.next_pending(0), 36);
+        assert_eq!(timer.next_pending(33), 36);
+
+        timer.write(8, 0xff07, 0b001);
+        assert_eq!(timer.next_pending(8), u64::MAX);
     }
 }
@@ -1,6 +1,10 @@
+use std::cell::Cell;
+
 use super::{Interrupt, InterruptMask, InterruptSource};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 const OAM_SLOTS: usize = 40;
+const OBJS_PER_LINE: usize = 10;
 const LCD_X: usize = 160;
 const LCD_Y: usize = 144;
 const CYCLES_PER_LINE: u64 = 456;
@@ -94,15 +98,32 @@ impl Lcdc {
 enum Mode {
     HBlank,
     VBlank,
-    Drawing,
     OamScan,
+    Drawing,
 }
 
+impl Mode {
+    /// The STAT bit that selects this mode as interrupt source
+    fn stat_select(&self) -> u8 {
+        match self {
+            Self::HBlank => 0b0000_1000,
+            Self::VBlank => 0b0001_0000,
+            Self::OamScan => 0b0010_0000,
+            Self::Drawing => 0,
+        }
+    }
+}
+
+const STAT_SELECT_LYC: u8 = 0b0100_0000;
+
+#[derive(Clone)]
 pub struct Video {
     framebuffer: [u8; LCD_X * LCD_Y],
     enable_cycle: u64,
     render_cycle: u64,
     lcdc: Lcdc,
+    /// The STAT interrupt select bits 3-6
+    stat: u8,
     scy: u8,
     scx: u8,
     lyc: u8,
@@ -111,11 +132,21 @@ pub struct Video {
     obp1: u8,
     wy: u8,
     wx: u8,
+    /// The window has its own line counter, which only advances on lines it was drawn on
+    window_line: u8,
+    /// LY matched WY during this frame, so the window is drawn from now on
+    window_triggered: bool,
+    /// The window was drawn with WX=166 on the previous line, so it spans the whole line
+    window_wrapped: bool,
     video_ram: [u8; 8192],
     oam: [u8; OAM_SLOTS * 4],
     irq_vblank_pending: bool,
     irq_stat_pending: bool,
     irq_acknowledge_cycle: u64,
+    /// Rising edges of the STAT interrupt line up to this cycle are included in `irq_stat_pending`
+    stat_checked_cycle: u64,
+    /// Cache for `next_stat_edge`: the first edge after the first cycle is the second one
+    stat_edge: Cell<Option<(u64, u64)>>,
 }
 
 impl Video {
@@ -125,6 +156,7 @@ impl Video {
             render_cycle: 0,
             enable_cycle: 0,
             lcdc: Lcdc(0b1000_0000),
+            stat: 0,
             scy: 0,
             scx: 0,
             lyc: 0,
@@ -133,11 +165,16 @@ impl Video {
             obp1: 0,
             wy: 0,
             wx: 0,
+            window_line: 0,
+            window_triggered: false,
+            window_wrapped: false,
             video_ram: [0u8; 8192],
             oam: [0u8; OAM_SLOTS * 4],
             irq_vblank_pending: false,
             irq_stat_pending: false,
             irq_acknowledge_cycle: 0,
+            stat_checked_cycle: 0,
+            stat_edge: Cell::new(None),
         }
     }
 
@@ -146,12 +183,180 @@ impl Video {
         &self.framebuffer
     }
 
-    fn cycle_in_frame(&self, cycle: u64) -> u64 {
-        cycle.saturating_sub(self.enable_cycle) % CYCLES_PER_FRAME
+    pub(crate) fn lcd_enabled(&self) -> bool {
+        self.enable_cycle != u64::MAX
+    }
+
+    /// Stop the PPU for the STOP instruction. The framebuffer keeps the
+    /// lines drawn so far and no vblank happens until `resume`.
+    pub(crate) fn stop(&mut self, cycle: u64) {
+        self.render_until(cycle);
+        self.change_stat_line(cycle, |video| {
+            video.enable_cycle = u64::MAX;
+            video.render_cycle = u64::MAX;
+        });
+    }
+
+    /// Restart the PPU at the first line, like after enabling the LCD
+    pub(crate) fn resume(&mut self, cycle: u64) {
+        if self.lcdc.lcd_enable() {
+            self.change_stat_line(cycle, |video| {
+                video.enable_cycle = cycle;
+                video.render_cycle = cycle;
+            });
+        }
+    }
+
+    /// Make sure the PPU timing restored from a save state fits the CPU `cycle`.
+    /// Rendering relies on `render_cycle` pointing at the start of a visible line
+    /// that was not drawn yet. It may lag behind, `render_until` skips old frames.
+    pub(crate) fn check_state(&self, cycle: u64) -> Result<(), StateError> {
+        if !self.lcd_enabled() {
+            // The LCD is off or the PPU is stopped by STOP
+            return match self.render_cycle {
+                u64::MAX => Ok(()),
+                _ => Err(StateError::Invalid("PPU timing")),
+            };
+        }
+
+        let consistent = self.lcdc.lcd_enable()
+            && self.enable_cycle <= cycle
+            && self.render_cycle >= self.enable_cycle
+            && self.render_cycle <= cycle.saturating_add(10 * CYCLES_PER_LINE)
+            && self.cycle_in_line(self.render_cycle) == 0
+            && self.line(self.render_cycle) < (LCD_Y as _);
+
+        match consistent {
+            true => Ok(()),
+            false => Err(StateError::Invalid("PPU timing")),
+        }
+    }
+
+    fn in_vblank(&self, cycle: u64) -> bool {
+        self.lcd_enabled() && self.mode(cycle) == Mode::VBlank
+    }
+
+    /// The first cycle after `cycle` at which a vblank period starts
+    pub(crate) fn next_vblank(&self, cycle: u64) -> u64 {
+        if !self.lcd_enabled() {
+            return u64::MAX;
+        }
+
+        let vblank_start = self.vblank_start(cycle);
+
+        if vblank_start > cycle {
+            vblank_start
+        } else {
+            vblank_start + CYCLES_PER_FRAME
+        }
+    }
+
+    /// The cycle at which the vblank period of the current frame starts (or started)
+    fn vblank_start(&self, cycle: u64) -> u64 {
+        let vblank_in_frame = (LCD_Y as u64) * CYCLES_PER_LINE;
+
+        cycle - self.cycle_in_frame(cycle) + vblank_in_frame
+    }
+
+    /// The vblank interrupt of the current frame was raised and was not
+    /// acknowledged since
+    fn vblank_raised(&self, cycle: u64) -> bool {
+        self.in_vblank(cycle) && self.irq_acknowledge_cycle < self.vblank_start(cycle)
+    }
+
+    /// The STAT interrupt line, the OR of all selected interrupt sources
+    fn stat_line(&self, cycle: u64) -> bool {
+        if !self.lcd_enabled() {
+            return false;
+        }
+
+        let lyc = if self.line(cycle) == self.lyc {
+            STAT_SELECT_LYC
+        } else {
+            0
+        };
+
+        self.stat & (self.mode(cycle).stat_select() | lyc) != 0
     }
 
-    fn frame(&self, cycle: u64) -> u64 {
-        cycle.saturating_sub(self.enable_cycle) / CYCLES_PER_FRAME
+    /// The first cycle after `cycle` at which the mode or line changes
+    fn next_mode_change(&self, cycle: u64) -> u64 {
+        let cycle_in_line = self.cycle_in_line(cycle);
+        let line_start = cycle - cycle_in_line;
+
+        let next = if self.line(cycle) >= (LCD_Y as _) || cycle_in_line >= 252 {
+            CYCLES_PER_LINE
+        } else if cycle_in_line >= 80 {
+            252
+        } else {
+            80
+        };
+
+        line_start + next
+    }
+
+    /// The first cycle after `cycle` at which the STAT interrupt line rises.
+    /// The interrupt is only raised on rising edges, so a source that becomes
+    /// active while another one already holds the line high does not raise it again.
+    pub(super) fn next_stat_edge(&self, cycle: u64) -> u64 {
+        // There is no edge between the cached query and its edge,
+        // so the result holds for every cycle in between.
+        if let Some((from, edge)) = self.stat_edge.get() {
+            if from <= cycle && cycle < edge {
+                return edge;
+            }
+        }
+
+        let edge = self.find_stat_edge(cycle);
+        self.stat_edge.set(Some((cycle, edge)));
+        edge
+    }
+
+    fn find_stat_edge(&self, cycle: u64) -> u64 {
+        if !self.lcd_enabled() || self.stat == 0 {
+            return u64::MAX;
+        }
+
+        // The line repeats every frame, so there is an edge within the next frame or never
+        let end = cycle + CYCLES_PER_FRAME + CYCLES_PER_LINE;
+        let mut high = self.stat_line(cycle);
+        let mut cycle = cycle;
+
+        while cycle <= end {
+            cycle = self.next_mode_change(cycle);
+
+            let now = self.stat_line(cycle);
+
+            if now && !high {
+                return cycle;
+            }
+
+            high = now;
+        }
+
+        u64::MAX
+    }
+
+    /// The STAT interrupt line rose after `stat_checked_cycle` and up to `cycle`
+    fn stat_raised(&self, cycle: u64) -> bool {
+        self.next_stat_edge(self.stat_checked_cycle) <= cycle
+    }
+
+    /// Apply `change` to registers that the STAT interrupt line depends on,
+    /// raising the interrupt if that makes the line rise.
+    fn change_stat_line(&mut self, cycle: u64, change: impl FnOnce(&mut Self)) {
+        self.irq_stat_pending |= self.stat_raised(cycle);
+        let before = self.stat_line(cycle);
+
+        change(self);
+
+        self.irq_stat_pending |= !before && self.stat_line(cycle);
+        self.stat_checked_cycle = cycle;
+        self.stat_edge.set(None);
+    }
+
+    fn cycle_in_frame(&self, cycle: u64) -> u64 {
+        cycle.saturating_sub(self.enable_cycle) % CYCLES_PER_FRAME
     }
 
     fn cycle_in_line(&self, cycle: u64) -> u64 {
@@ -222,7 +427,7 @@ impl Video {
         (tile_data_l, tile_data_h)
     }
 
-    fn draw_background_line(&mut self) {
+    fn draw_background_line(&mut self, bg_idx: &mut [u8; LCD_X]) {
         let lcd_y = self.line(self.render_cycle);
         let tile_map_base = self.lcdc.background_tile_map_base();
 
@@ -249,25 +454,39 @@ impl Video {
             let idx = (lcd_y as usize) * 160 + lcd_x as usize;
 
             self.framebuffer[idx] = val;
+            bg_idx[lcd_x as usize] = pal_idx;
         }
     }
 
-    fn draw_window_line(&mut self) {
+    fn draw_window_line(&mut self, bg_idx: &mut [u8; LCD_X]) {
         let lcd_y = self.line(self.render_cycle);
         let tile_map_base = self.lcdc.window_tile_map_base();
 
-        let window_y = (lcd_y as i16) - (self.wy as i16);
-
-        if window_y < 0 {
+        // WX values above 166 move the window off screen, it is not drawn on this line
+        if self.wx > 166 {
+            self.window_wrapped = false;
             return;
         }
 
-        let window_y = window_y as u8;
+        let window_y = self.window_line;
         let tile_y = window_y / 8;
         let in_tile_y = window_y % 8;
 
+        // The window starts at WX-7. After a line with WX=166 it starts at the left edge
+        // and with WX=0 the start is delayed by the fine horizontal scroll.
+        let window_start = if self.window_wrapped {
+            0
+        } else if self.wx == 0 {
+            (self.scx % 8) as i16 - 7
+        } else {
+            self.wx as i16 - 7
+        };
+
+        self.window_line = self.window_line.wrapping_add(1);
+        self.window_wrapped = self.wx == 166;
+
         for lcd_x in 0..(LCD_X as u8) {
-            let window_x = (lcd_x as i16) - (self.wx as i16) + 7;
+            let window_x = (lcd_x as i16) - window_start;
 
             if window_x < 0 {
                 continue;
@@ -291,85 +510,149 @@ impl Video {
             let idx = (lcd_y as usize) * LCD_X + lcd_x as usize;
 
             self.framebuffer[idx] = val;
+            bg_idx[lcd_x as usize] = pal_idx;
         }
     }
 
-    fn draw_obj_line(&mut self) {
-        assert!(self.lcdc.obj_size() == 8);
-
+    /// Draw the objects on this line over the background with the raw
+    /// color indices `bg_idx`, which decide if objects behind it are visible.
+    fn draw_obj_line(&mut self, bg_idx: &[u8; LCD_X]) {
         let lcd_y = self.line(self.render_cycle);
+        let height = self.lcdc.obj_size() as i16;
 
-        for idx in 0..(OAM_SLOTS as _) {
-            let obj = self.oam_entry(idx);
+        // The OAM scan selects the first ten objects in OAM order that cover this line,
+        // no matter if they are horizontally on screen.
+        let mut selected = [0u8; OBJS_PER_LINE];
+        let mut count = 0;
 
-            let in_obj_y = (lcd_y as i16) - (obj.y as i16) + 16;
+        for idx in 0..(OAM_SLOTS as u8) {
+            if count == OBJS_PER_LINE {
+                break;
+            }
 
-            if !(0..8).contains(&in_obj_y) {
-                continue;
+            let in_obj_y = (lcd_y as i16) - (self.oam_entry(idx).y as i16) + 16;
+
+            if (0..height).contains(&in_obj_y) {
+                selected[count] = idx;
+                count += 1;
             }
+        }
 
-            let in_obj_y = match obj.flip_y() {
-                true => (7 - in_obj_y) as u8,
-                false => in_obj_y as u8,
-            };
+        // Objects with a lower X coordinate are on top, for equal X the one first in OAM.
+        let selected = &mut selected[..count];
+        selected.sort_by_key(|idx| (self.oam_entry(*idx).x, *idx));
 
-            let (tile_data_l, tile_data_h) = self.get_obj_tile_row(obj.idx, in_obj_y);
+        // The pixels an object with higher priority already claimed. This also holds
+        // for pixels of objects behind the background, which hide lower priority objects.
+        let mut obj_drawn = [false; LCD_X];
 
-            let pal = match obj.obp1() {
-                true => self.obp1,
-                false => self.obp0,
-            };
+        for idx in selected.iter() {
+            self.draw_obj(*idx, lcd_y, height, bg_idx, &mut obj_drawn);
+        }
+    }
 
-            for in_obj_x in 0..8 {
-                let lcd_x = in_obj_x + (obj.x as i16) - 8;
+    fn draw_obj(
+        &mut self,
+        idx: u8,
+        lcd_y: u8,
+        height: i16,
+        bg_idx: &[u8; LCD_X],
+        obj_drawn: &mut [bool; LCD_X],
+    ) {
+        let obj = self.oam_entry(idx);
 
-                if !(0..(LCD_X as _)).contains(&lcd_x) {
-                    continue;
-                }
+        let in_obj_y = (lcd_y as i16) - (obj.y as i16) + 16;
 
-                let in_obj_x = match obj.flip_x() {
-                    true => (7 - in_obj_x) as u8,
-                    false => in_obj_x as u8,
-                };
+        let in_obj_y = match obj.flip_y() {
+            true => (height - 1 - in_obj_y) as u8,
+            false => in_obj_y as u8,
+        };
+
+        // 8x16 objects use an even and the following odd tile, the LSB of the index is ignored
+        let tile_idx = match height {
+            16 => obj.idx & 0xfe,
+            _ => obj.idx,
+        };
+
+        let (tile_data_l, tile_data_h) = self.get_obj_tile_row(tile_idx, in_obj_y);
 
-                let bit_l = (tile_data_l.wrapping_shl(in_obj_x as u32)) & 0b1000_0000 != 0;
-                let bit_h = (tile_data_h.wrapping_shl(in_obj_x as u32)) & 0b1000_0000 != 0;
+        let pal = match obj.obp1() {
+            true => self.obp1,
+            false => self.obp0,
+        };
+
+        for in_obj_x in 0..8 {
+            let lcd_x = in_obj_x + (obj.x as i16) - 8;
 
-                let pal_idx = (bit_h as u8) << 1 | (bit_l as u8);
+            if !(0..(LCD_X as _)).contains(&lcd_x) {
+                continue;
+            }
 
-                if pal_idx != 0 {
-                    let val = (pal >> (pal_idx * 2)) & 0b0000_0011;
+            let in_obj_x = match obj.flip_x() {
+                true => (7 - in_obj_x) as u8,
+                false => in_obj_x as u8,
+            };
 
-                    let idx = (lcd_y as usize) * 160 + lcd_x as usize;
+            let bit_l = (tile_data_l.wrapping_shl(in_obj_x as u32)) & 0b1000_0000 != 0;
+            let bit_h = (tile_data_h.wrapping_shl(in_obj_x as u32)) & 0b1000_0000 != 0;
 
-                    // TODO: This background priority implementation is not correct,
-                    // as overlapping objects also trigger it, but ... eh.
-                    let bg_recessive = self.framebuffer[idx] == self.bgp & 0b0000_0011;
+            let pal_idx = (bit_h as u8) << 1 | (bit_l as u8);
 
-                    if !obj.below_bg() || bg_recessive {
-                        self.framebuffer[idx] = val;
-                    }
+            if pal_idx != 0 && !obj_drawn[lcd_x as usize] {
+                let val = (pal >> (pal_idx * 2)) & 0b0000_0011;
+
+                let idx = (lcd_y as usize) * 160 + lcd_x as usize;
+
+                obj_drawn[lcd_x as usize] = true;
+
+                // Objects behind the background are only visible where it uses color 0
+                if !obj.below_bg() || bg_idx[lcd_x as usize] == 0 {
+                    self.framebuffer[idx] = val;
                 }
             }
         }
     }
 
     fn draw_line(&mut self) {
+        let lcd_y = self.line(self.render_cycle);
+        let mut bg_idx = [0u8; LCD_X];
+
+        if lcd_y == 0 {
+            self.window_line = 0;
+            self.window_triggered = false;
+        }
+
+        self.window_triggered |= lcd_y == self.wy;
+
         if self.lcdc.bw_win_enable() {
-            self.draw_background_line();
+            self.draw_background_line(&mut bg_idx);
+        } else {
+            // Background and window are white and objects are always in front
+            let line_start = (lcd_y as usize) * LCD_X;
+            self.framebuffer[line_start..line_start + LCD_X].fill(0);
+        }
 
-            if self.lcdc.window_enable() {
-                self.draw_window_line();
-            }
+        if self.lcdc.bw_win_enable() && self.lcdc.window_enable() && self.window_triggered {
+            self.draw_window_line(&mut bg_idx);
+        } else {
+            self.window_wrapped = false;
         }
 
         if self.lcdc.obj_enable() {
-            self.draw_obj_line();
+            self.draw_obj_line(&bg_idx);
         }
     }
 
     fn render_until(&mut self, cycle: u64) {
-        while self.render_cycle.saturating_add(CYCLES_PER_LINE) < cycle {
+        // Lines are drawn using the current registers and memory, so older frames
+        // would only be overwritten by identical ones. Draw at most the last two.
+        let frames_behind = cycle.saturating_sub(self.render_cycle) / CYCLES_PER_FRAME;
+
+        if frames_behind > 1 {
+            self.render_cycle += (frames_behind - 1) * CYCLES_PER_FRAME;
+        }
+
+        while self.render_cycle.saturating_add(CYCLES_PER_LINE) <= cycle {
             assert!(self.cycle_in_line(self.render_cycle) == 0);
             assert!(self.line(self.render_cycle) < (LCD_Y as _));
 
@@ -396,9 +679,14 @@ impl Video {
             0xff40 => self.lcdc.0,
             0xff41 => {
                 let lym = self.lyc == self.line(cycle);
-                let mode = self.mode(cycle) as u8;
+                // The mode reads as 0 while the LCD is off
+                let mode = if self.lcd_enabled() {
+                    self.mode(cycle) as u8
+                } else {
+                    Mode::HBlank as u8
+                };
 
-                (lym as u8) << 2 | mode
+                0b1000_0000 | self.stat | (lym as u8) << 2 | mode
             }
             0xff42 => self.scy,
             0xff43 => self.scx,
@@ -416,6 +704,32 @@ impl Video {
         }
     }
 
+    /// Set a register without raising a STAT interrupt.
+    /// The LCD enable bit of LCDC is kept, as switching the LCD on or off restarts the PPU.
+    pub(super) fn poke(&mut self, cycle: u64, addr: u16, val: u8) {
+        self.render_until(cycle);
+
+        // Edges up to now still count, but the new register values do not cause one
+        self.irq_stat_pending |= self.stat_raised(cycle);
+
+        match addr {
+            0xff40 => {
+                let enable = self.lcdc.0 & 0b1000_0000;
+                self.lcdc = Lcdc(val & 0b0111_1111 | enable);
+            }
+            0xff41 => {
+                self.stat = val & 0b0111_1000;
+            }
+            0xff45 => {
+                self.lyc = val;
+            }
+            _ => self.write(cycle, addr, val),
+        }
+
+        self.stat_checked_cycle = cycle;
+        self.stat_edge.set(None);
+    }
+
     pub(super) fn write(&mut self, cycle: u64, addr: u16, val: u8) {
         self.render_until(cycle);
 
@@ -428,27 +742,24 @@ impl Video {
                 let offset = (addr as usize) - 0xfe00;
                 self.oam[offset] = val;
             }
-            0xff40 => {
-                let en_pre = self.lcdc.lcd_enable();
-                self.lcdc = Lcdc(val);
-                let en_post = self.lcdc.lcd_enable();
+            0xff40 => self.change_stat_line(cycle, |video| {
+                let en_pre = video.lcdc.lcd_enable();
+                video.lcdc = Lcdc(val);
+                let en_post = video.lcdc.lcd_enable();
 
                 if !en_pre && en_post {
-                    self.enable_cycle = cycle;
-                    self.render_cycle = cycle;
+                    video.enable_cycle = cycle;
+                    video.render_cycle = cycle;
                 }
 
                 if en_pre && !en_post {
-                    self.enable_cycle = u64::MAX;
-                    self.render_cycle = u64::MAX;
+                    video.enable_cycle = u64::MAX;
+                    video.render_cycle = u64::MAX;
                 }
-            }
-            0xff41 => {
-                if val != 0 {
-                    // TODO: if implemented also needs changes in read_u8
-                    unimplemented!("writing STAT != 0 (e.g. 0x{val:02x})")
-                }
-            }
+            }),
+            0xff41 => self.change_stat_line(cycle, |video| {
+                video.stat = val & 0b0111_1000;
+            }),
             0xff42 => {
                 self.scy = val;
             }
@@ -456,9 +767,9 @@ impl Video {
                 self.scx = val;
             }
             0xff44 => {}
-            0xff45 => {
-                self.lyc = val;
-            }
+            0xff45 => self.change_stat_line(cycle, |video| {
+                video.lyc = val;
+            }),
             0xff46 => {
                 panic!("OAM DMA should be handled at the peripherial level");
             }
@@ -486,11 +797,11 @@ impl InterruptSource for Video {
     fn pending(&self, cycle: u64) -> InterruptMask {
         let mut res = InterruptMask::default();
 
-        if self.irq_vblank_pending || cycle >= self.next_pending(cycle) {
+        if self.irq_vblank_pending || self.vblank_raised(cycle) {
             res.set(Interrupt::VBlank)
         }
 
-        if self.irq_stat_pending {
+        if self.irq_stat_pending || self.stat_raised(cycle) {
             res.set(Interrupt::Lcd)
         }
 
@@ -500,6 +811,8 @@ impl InterruptSource for Video {
     fn set_pending(&mut self, cycle: u64, mask: InterruptMask) {
         self.irq_vblank_pending = mask.is_set(Interrupt::VBlank);
         self.irq_stat_pending = mask.is_set(Interrupt::Lcd);
+        self.stat_checked_cycle = cycle;
+        self.stat_edge.set(None);
 
         if !self.irq_vblank_pending {
             self.irq_acknowledge_cycle = cycle;
@@ -507,22 +820,151 @@ impl InterruptSource for Video {
     }
 
     fn next_pending(&self, cycle: u64) -> u64 {
-        if self.enable_cycle == u64::MAX {
-            return u64::MAX;
-        }
+        self.next_vblank(cycle).min(self.next_stat_edge(cycle))
+    }
+}
 
-        let in_vblank = self.mode(cycle) == Mode::VBlank;
-        let vblank_acknowledged = self.frame(cycle) == self.frame(self.irq_acknowledge_cycle);
+impl Snapshot for Video {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.framebuffer);
+        writer.put(&self.enable_cycle);
+        writer.put(&self.render_cycle);
+        writer.put(&self.lcdc.0);
+        writer.put(&self.stat);
+        writer.put(&[
+            self.scy, self.scx, self.lyc, self.bgp, self.obp0, self.obp1, self.wy, self.wx,
+        ]);
+        writer.put(&self.window_line);
+        writer.put(&self.window_triggered);
+        writer.put(&self.window_wrapped);
+        writer.put(&self.video_ram);
+        writer.put(&self.oam);
+        writer.put(&self.irq_vblank_pending);
+        writer.put(&self.irq_stat_pending);
+        writer.put(&self.irq_acknowledge_cycle);
+        writer.put(&self.stat_checked_cycle);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.framebuffer = reader.get()?;
+        self.enable_cycle = reader.get()?;
+        self.render_cycle = reader.get()?;
+        self.lcdc = Lcdc(reader.get()?);
+        self.stat = reader.get::<u8>()? & 0b0111_1000;
+
+        let [scy, scx, lyc, bgp, obp0, obp1, wy, wx] = reader.get()?;
+        self.scy = scy;
+        self.scx = scx;
+        self.lyc = lyc;
+        self.bgp = bgp;
+        self.obp0 = obp0;
+        self.obp1 = obp1;
+        self.wy = wy;
+        self.wx = wx;
+
+        self.window_line = reader.get()?;
+        self.window_triggered = reader.get()?;
+        self.window_wrapped = reader.get()?;
+        self.video_ram = reader.get()?;
+        self.oam = reader.get()?;
+        self.irq_vblank_pending = reader.get()?;
+        self.irq_stat_pending = reader.get()?;
+        self.irq_acknowledge_cycle = reader.get()?;
+        self.stat_checked_cycle = reader.get()?;
+        self.stat_edge.set(None);
+
+        Ok(())
+    }
+}
 
-        if in_vblank && !vblank_acknowledged {
-            return cycle;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let vblank_in_frame = (LCD_Y as u64) * CYCLES_PER_LINE;
-        let vblank_in_next_frame = vblank_in_frame + CYCLES_PER_FRAME;
-        let cycles_till_vblank = vblank_in_next_frame - self.cycle_in_frame(cycle);
-        let ctv_wrapped = cycles_till_vblank % CYCLES_PER_FRAME;
+    #[test]
+    fn render_timing_is_checked_against_cpu_cycle() {
+        let mut video = Video::new();
+        video.write(1000, 0xff40, 0x00);
+        video.write(2000, 0xff40, 0x91);
+
+        assert_eq!(video.check_state(2000), Ok(()));
+        assert_eq!(video.check_state(u64::MAX), Ok(()));
+        assert!(video.check_state(1999).is_err());
+
+        video.render_cycle = 2001;
+        assert!(video.check_state(5000).is_err());
+
+        video.render_cycle = 2000 + 20 * CYCLES_PER_LINE;
+        assert!(video.check_state(2000).is_err());
+    }
+
+    #[test]
+    fn lcd_off_state_must_not_render() {
+        let mut video = Video::new();
+        video.write(1000, 0xff40, 0x00);
+        assert_eq!(video.check_state(1000), Ok(()));
+
+        video.render_cycle = 0;
+        assert!(video.check_state(1000).is_err());
+    }
+
+    #[test]
+    fn rendering_far_behind_skips_old_frames() {
+        let mut video = Video::new();
+        let cycle = u64::MAX / 2;
+
+        assert_eq!(video.check_state(cycle), Ok(()));
+
+        video.render_until(cycle);
+        assert!(video.render_cycle + CYCLES_PER_FRAME > cycle);
+        assert!(video.render_cycle <= cycle.saturating_add(10 * CYCLES_PER_LINE));
+    }
+
+    #[test]
+    fn stat_interrupt_rises_on_lyc_match() {
+        let mut video = Video::new();
+        video.write(0, 0xff45, 5);
+        video.write(0, 0xff41, STAT_SELECT_LYC);
+
+        let line_5 = 5 * CYCLES_PER_LINE;
+        assert_eq!(video.next_stat_edge(0), line_5);
+        assert_eq!(video.next_stat_edge(line_5), line_5 + CYCLES_PER_FRAME);
+
+        assert!(!video.pending(line_5 - 1).is_set(Interrupt::Lcd));
+        assert!(video.pending(line_5).is_set(Interrupt::Lcd));
+    }
+
+    #[test]
+    fn stat_interrupt_is_blocked_while_line_is_high() {
+        let mut video = Video::new();
+        video.write(0, 0xff45, 1);
+        video.write(0, 0xff41, Mode::HBlank.stat_select());
+
+        // Without LYC every hblank raises the interrupt
+        assert_eq!(video.next_stat_edge(252), CYCLES_PER_LINE + 252);
+
+        // The LYC match on line 1 follows the hblank of line 0 without a gap
+        // and holds the line high through the hblank of line 1
+        video.write(0, 0xff41, Mode::HBlank.stat_select() | STAT_SELECT_LYC);
+        assert_eq!(video.next_stat_edge(0), 252);
+        assert_eq!(video.next_stat_edge(252), 2 * CYCLES_PER_LINE + 252);
+    }
+
+    #[test]
+    fn stat_edge_is_cached_until_registers_change() {
+        let mut video = Video::new();
+        video.write(0, 0xff45, 5);
+        video.write(0, 0xff41, STAT_SELECT_LYC);
+
+        let line_5 = 5 * CYCLES_PER_LINE;
+        assert_eq!(video.next_stat_edge(10), line_5);
+        assert_eq!(video.stat_edge.get(), Some((10, line_5)));
+
+        assert_eq!(video.next_stat_edge(line_5 - 1), line_5);
+        assert_eq!(video.stat_edge.get(), Some((10, line_5)));
 
-        cycle + ctv_wrapped
+        video.write(100, 0xff45, 6);
+        assert_eq!(video.stat_edge.get(), None);
+        assert_eq!(video.next_stat_edge(100), line_5 + CYCLES_PER_LINE);
     }
 }
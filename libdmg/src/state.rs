@@ -0,0 +1,187 @@
+use std::fmt;
+
+const MAGIC: [u8; 8] = *b"LIBDMGST";
+
+/// Bumped whenever the layout of the serialized state changes
+const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state magic bytes
+    NotAState,
+    /// The state was written by an incompatible version of libdmg
+    UnsupportedVersion(u16),
+    /// The state was created for a different ROM
+    WrongRom,
+    /// The data ended before the state was complete
+    Truncated,
+    /// The data contains a value that is not valid at this position
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAState => write!(f, "data is not a save state"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported save state version {v}"),
+            Self::WrongRom => write!(f, "save state belongs to a different ROM"),
+            Self::Truncated => write!(f, "save state is truncated"),
+            Self::Invalid(what) => write!(f, "save state contains an invalid {what}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new(rom_hash: u64) -> Self {
+        let mut writer = Self { buf: Vec::new() };
+
+        writer.put(&MAGIC);
+        writer.put(&VERSION);
+        writer.put(&rom_hash);
+
+        writer
+    }
+
+    pub(crate) fn put<T: StateValue>(&mut self, val: &T) {
+        val.write(self);
+    }
+
+    pub(crate) fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8], rom_hash: u64) -> Result<Self, StateError> {
+        let mut reader = Self { data };
+
+        if reader.get::<[u8; 8]>() != Ok(MAGIC) {
+            return Err(StateError::NotAState);
+        }
+
+        let version: u16 = reader.get()?;
+
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        if reader.get::<u64>()? != rom_hash {
+            return Err(StateError::WrongRom);
+        }
+
+        Ok(reader)
+    }
+
+    pub(crate) fn get<T: StateValue>(&mut self) -> Result<T, StateError> {
+        T::read(self)
+    }
+
+    pub(crate) fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::Invalid("trailing data")),
+        }
+    }
+}
+
+/// A value that can be written to and read back from a save state
+pub(crate) trait StateValue: Sized {
+    fn write(&self, writer: &mut StateWriter);
+    fn read(reader: &mut StateReader) -> Result<Self, StateError>;
+}
+
+macro_rules! int_state_value {
+    ($($t:ty),*) => {
+        $(
+            impl StateValue for $t {
+                fn write(&self, writer: &mut StateWriter) {
+                    writer.put_bytes(&self.to_le_bytes());
+                }
+
+                fn read(reader: &mut StateReader) -> Result<Self, StateError> {
+                    let bytes = reader.get_bytes(std::mem::size_of::<Self>())?;
+                    Ok(Self::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+int_state_value!(u8, u16, u32, u64);
+
+impl StateValue for bool {
+    fn write(&self, writer: &mut StateWriter) {
+        writer.put(&(*self as u8));
+    }
+
+    fn read(reader: &mut StateReader) -> Result<Self, StateError> {
+        match reader.get::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+}
+
+impl<const N: usize> StateValue for [u8; N] {
+    fn write(&self, writer: &mut StateWriter) {
+        writer.put_bytes(self);
+    }
+
+    fn read(reader: &mut StateReader) -> Result<Self, StateError> {
+        let bytes = reader.get_bytes(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+}
+
+impl StateValue for Option<u64> {
+    fn write(&self, writer: &mut StateWriter) {
+        writer.put(&self.is_some());
+        writer.put(&self.unwrap_or_default());
+    }
+
+    fn read(reader: &mut StateReader) -> Result<Self, StateError> {
+        let is_some: bool = reader.get()?;
+        let val: u64 = reader.get()?;
+
+        Ok(is_some.then_some(val))
+    }
+}
+
+/// A part of the machine whose state can be saved and restored
+pub(crate) trait Snapshot {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// FNV-1a hash used to make sure states are only loaded for the ROM they were created with
+pub(crate) fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ (*byte as u64)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
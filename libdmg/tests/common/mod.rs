@@ -0,0 +1,16 @@
+#![allow(dead_code)]
+
+use libdmg::{Cartridge, Dmg};
+
+/// A 32KiB ROM-only cartridge image filled with NOPs
+pub fn rom() -> Vec<u8> {
+    vec![0; 0x8000]
+}
+
+/// A machine that executes `code` from the boot ROM, which is mapped at 0x0000
+pub fn dmg_with_code(code: &[u8]) -> Dmg {
+    let mut bootrom = code.to_vec();
+    bootrom.resize(256, 0);
+
+    Dmg::new(bootrom, Cartridge::new(rom(), None).unwrap()).unwrap()
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use libdmg::StopReason;
+
+#[test]
+fn halt_with_only_disabled_sources_upcoming_never_wakes() {
+    // IE = timer only, TAC off, LCD on, HALT.
+    // VBlank and STAT keep happening, but can not wake the CPU.
+    let mut dmg = common::dmg_with_code(&[
+        0x3e, 0x04, // ld a, $04
+        0xe0, 0xff, // ldh [$ff], a
+        0x3e, 0x91, // ld a, $91
+        0xe0, 0x40, // ldh [$40], a
+        0x76, // halt
+    ]);
+
+    let mut calls = 0;
+    let reason = dmg
+        .run_until(|_| {
+            calls += 1;
+            calls > 10_000
+        })
+        .unwrap();
+
+    assert_eq!(reason, StopReason::Halted);
+    assert!(dmg.cpu_state().halted);
+}
+
+#[test]
+fn halt_wakes_on_enabled_interrupt_without_ime() {
+    // IE = vblank, IME off: HALT ends at vblank and execution continues after it
+    let mut dmg = common::dmg_with_code(&[
+        0x3e, 0x01, // ld a, $01
+        0xe0, 0xff, // ldh [$ff], a
+        0x3e, 0x91, // ld a, $91
+        0xe0, 0x40, // ldh [$40], a
+        0x76, // halt
+        0x00, // nop
+        0x00, // nop
+    ]);
+
+    assert_eq!(dmg.run_until_pc(0x000a).unwrap(), StopReason::TargetReached);
+    assert!(!dmg.cpu_state().halted);
+    assert_eq!(dmg.peek(0xff44), 144);
+}
+
+#[test]
+fn halt_wakes_on_timer_reload() {
+    // TIMA = $fe, 16 cycle clock, IE = timer: HALT ends after two TIMA ticks
+    let mut dmg = common::dmg_with_code(&[
+        0x3e, 0xfe, // ld a, $fe
+        0xe0, 0x05, // ldh [$05], a
+        0x3e, 0x04, // ld a, $04
+        0xe0, 0xff, // ldh [$ff], a
+        0x3e, 0x05, // ld a, $05
+        0xe0, 0x07, // ldh [$07], a
+        0x76, // halt
+        0x00, // nop
+        0x00, // nop
+    ]);
+
+    assert_eq!(dmg.run_until_pc(0x000e).unwrap(), StopReason::TargetReached);
+    assert!(!dmg.cpu_state().halted);
+    assert_ne!(dmg.peek(0xff0f) & 0x04, 0);
+    assert_eq!(dmg.peek(0xff05), 0x00);
+}
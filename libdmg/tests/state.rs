@@ -0,0 +1,98 @@
+mod common;
+
+use libdmg::{Cartridge, Dmg, StateError};
+
+/// Turn the LCD off and on again, so that it is not enabled at cycle 0
+const LCD_RESTART: [u8; 7] = [
+    0xaf, // xor a
+    0xe0, 0x40, // ldh [$40], a
+    0x3e, 0x91, // ld a, $91
+    0xe0, 0x40, // ldh [$40], a
+];
+
+fn running_dmg() -> Dmg {
+    let mut code = LCD_RESTART.to_vec();
+    code.extend([0x18, 0xfe]); // jr -2
+
+    let mut dmg = common::dmg_with_code(&code);
+    dmg.run_frame(&[]).unwrap();
+    dmg.run_cycles(12345).unwrap();
+    dmg
+}
+
+#[test]
+fn state_round_trip() {
+    let mut dmg = running_dmg();
+    let state = dmg.save_state();
+    let cpu = dmg.cpu_state();
+
+    dmg.run_frame(&[]).unwrap();
+    dmg.load_state(&state).unwrap();
+
+    assert_eq!(dmg.cpu_state(), cpu);
+    assert_eq!(dmg.save_state(), state);
+}
+
+#[test]
+fn truncated_state_is_rejected() {
+    let mut dmg = running_dmg();
+    let state = dmg.save_state();
+
+    for len in [0, 4, 10, 20, state.len() / 2, state.len() - 1] {
+        let expected = match len {
+            0 | 4 => StateError::NotAState,
+            _ => StateError::Truncated,
+        };
+
+        assert_eq!(dmg.load_state(&state[..len]), Err(expected));
+    }
+
+    let mut extended = state.clone();
+    extended.push(0);
+    assert_eq!(
+        dmg.load_state(&extended),
+        Err(StateError::Invalid("trailing data"))
+    );
+}
+
+#[test]
+fn state_of_other_rom_is_rejected() {
+    let state = running_dmg().save_state();
+
+    let mut rom = common::rom();
+    rom[0x4000] = 0x01;
+
+    let mut bootrom = vec![0x18, 0xfe];
+    bootrom.resize(256, 0);
+    let mut other = Dmg::new(bootrom, Cartridge::new(rom, None).unwrap()).unwrap();
+
+    assert_eq!(other.load_state(&state), Err(StateError::WrongRom));
+}
+
+#[test]
+fn state_of_other_version_is_rejected() {
+    let mut dmg = running_dmg();
+    let mut state = dmg.save_state();
+    state[8..10].copy_from_slice(&0xffffu16.to_le_bytes());
+
+    assert_eq!(
+        dmg.load_state(&state),
+        Err(StateError::UnsupportedVersion(0xffff))
+    );
+}
+
+#[test]
+fn crafted_cpu_cycle_before_lcd_enable_is_rejected() {
+    let mut dmg = running_dmg();
+    let mut state = dmg.save_state();
+    let cpu = dmg.cpu_state();
+
+    // The CPU cycle directly follows the magic, version and ROM hash
+    state[18..26].copy_from_slice(&0u64.to_le_bytes());
+
+    assert_eq!(
+        dmg.load_state(&state),
+        Err(StateError::Invalid("PPU timing"))
+    );
+    assert_eq!(dmg.cpu_state(), cpu);
+}
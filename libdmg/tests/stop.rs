@@ -0,0 +1,57 @@
+mod common;
+
+use libdmg::{Button, StopReason};
+
+#[test]
+fn stop_waits_for_joypad_input() {
+    let mut dmg = common::dmg_with_code(&[
+        0x3e, 0x10, // ld a, $10 (select the action buttons)
+        0xe0, 0x00, // ldh [$00], a
+        0x10, 0x00, // stop
+        0x3c, // inc a
+        0x10, 0x00, // stop
+        0x3c, // inc a
+    ]);
+
+    for _ in 0..2 {
+        assert_eq!(dmg.step_instruction().unwrap(), StopReason::TargetReached);
+    }
+
+    assert_eq!(dmg.step_instruction().unwrap(), StopReason::Stopped);
+    assert_eq!(dmg.cpu_state().pc, 0x0006);
+    assert!(dmg.cpu_state().stopped);
+
+    // Without input the CPU stays in STOP mode, also for whole frames
+    assert_eq!(dmg.step_instruction().unwrap(), StopReason::Stopped);
+    assert_eq!(dmg.run_until(|_| true).unwrap(), StopReason::Stopped);
+
+    let frame = dmg.run_frame(&[]).unwrap();
+    assert!(!frame.new_frame);
+    assert!(dmg.cpu_state().stopped);
+
+    // A pressed button ends STOP mode and execution continues after the skipped byte.
+    // The next STOP sees the held button and enters HALT mode instead.
+    dmg.run_frame(&[Button::A]).unwrap();
+    assert!(!dmg.cpu_state().stopped);
+    assert!(dmg.cpu_state().halted);
+    assert_eq!(dmg.cpu_state().pc, 0x0009);
+    assert_eq!(dmg.cpu_state().af >> 8, 0x11);
+}
+
+#[test]
+fn stop_with_pending_interrupt_is_one_byte_long() {
+    let mut dmg = common::dmg_with_code(&[
+        0x3e, 0x04, // ld a, $04
+        0xe0, 0xff, // ldh [$ff], a
+        0xe0, 0x0f, // ldh [$0f], a
+        0x10, // stop
+        0x3c, // inc a
+    ]);
+
+    for _ in 0..3 {
+        dmg.step_instruction().unwrap();
+    }
+
+    assert_eq!(dmg.step_instruction().unwrap(), StopReason::Stopped);
+    assert_eq!(dmg.cpu_state().pc, 0x0007);
+}
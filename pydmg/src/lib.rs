@@ -1,44 +1,64 @@
-use numpy::PyArray2;
-use pyo3::{exceptions::PyValueError, prelude::*};
-
-#[pyclass]
-#[derive(Clone)]
-struct Cartridge(libdmg::Cartridge);
-
-#[pymethods]
-impl Cartridge {
-    #[new]
-    fn new(rom: Vec<u8>, sram: Option<Vec<u8>>) -> Self {
-        Self(libdmg::Cartridge::new(rom, sram))
-    }
-}
+use pyo3::prelude::*;
+
+use classes::{Cartridge, Dmg};
+
+// pyo3 0.20 expands every #[new] into a trait impl nested inside a function,
+// which trips this newer rustc lint. The level of the generated code follows
+// the enclosing item, not the #[pymethods] block, so the classes get a module.
+#[allow(non_local_definitions)]
+mod classes {
+    use numpy::PyArray2;
+    use pyo3::{
+        exceptions::{PyRuntimeError, PyValueError},
+        prelude::*,
+    };
 
-#[pyclass]
-struct Dmg(libdmg::Dmg);
+    #[pyclass]
+    #[derive(Clone)]
+    pub(crate) struct Cartridge(libdmg::Cartridge);
 
-#[pymethods]
-impl Dmg {
-    #[new]
-    fn new(bootrom: Vec<u8>, cartridge: Cartridge) -> Self {
-        Self(libdmg::Dmg::new(bootrom, cartridge.0))
+    #[pymethods]
+    impl Cartridge {
+        #[new]
+        fn new(rom: Vec<u8>, sram: Option<Vec<u8>>) -> PyResult<Self> {
+            libdmg::Cartridge::new(rom, sram)
+                .map(Self)
+                .map_err(|err| PyValueError::new_err(err.to_string()))
+        }
     }
 
-    fn run_frame(&mut self, framebuffer: &PyArray2<u8>) -> PyResult<()> {
-        if framebuffer.shape() != &[160, 144] {
-            return Err(PyValueError::new_err("framebuffer must have shape 160x144"))?;
+    #[pyclass]
+    pub(crate) struct Dmg(libdmg::Dmg);
+
+    #[pymethods]
+    impl Dmg {
+        #[new]
+        fn new(bootrom: Vec<u8>, cartridge: Cartridge) -> PyResult<Self> {
+            libdmg::Dmg::new(bootrom, cartridge.0)
+                .map(Self)
+                .map_err(|err| PyValueError::new_err(err.to_string()))
         }
 
-        let frame_src = self.0.run_frame(&[]);
+        fn run_frame(&mut self, framebuffer: &PyArray2<u8>) -> PyResult<bool> {
+            if framebuffer.shape() != [160, 144] {
+                return Err(PyValueError::new_err("framebuffer must have shape 160x144"))?;
+            }
 
-        let mut framebuffer = framebuffer.readwrite();
-        let frame_dst = framebuffer.as_slice_mut()?;
+            let frame = self
+                .0
+                .run_frame(&[])
+                .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
 
-        frame_dst
-            .iter_mut()
-            .zip(frame_src)
-            .for_each(|(dst, src)| *dst = *src);
+            let mut framebuffer = framebuffer.readwrite();
+            let frame_dst = framebuffer.as_slice_mut()?;
 
-        Ok(())
+            frame_dst
+                .iter_mut()
+                .zip(frame.framebuffer)
+                .for_each(|(dst, src)| *dst = *src);
+
+            Ok(frame.new_frame)
+        }
     }
 }
 
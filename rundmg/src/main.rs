@@ -34,21 +34,35 @@ fn main() -> anyhow::Result<()> {
     let mut dmg = {
         let rom = std::fs::read(args.rom)?;
         let bootrom = std::fs::read(args.bootrom)?;
-        let sram = args.save.and_then(|s| std::fs::read(s).ok());
+        let sram = args.save.as_ref().and_then(|s| std::fs::read(s).ok());
 
-        let cartridge = Cartridge::new(rom, sram);
+        let cartridge = Cartridge::new(rom, sram)?;
 
-        Dmg::new(bootrom, cartridge)
+        Dmg::new(bootrom, cartridge)?
     };
 
     loop {
         let buttons = window.buttons(&BUTTON_MAP);
 
-        let frame = dmg.run_frame(&buttons);
+        let frame = dmg.run_frame(&buttons)?;
 
-        if !window.update(frame.as_ref())? {
+        if !window.update(frame.framebuffer)? {
             break;
         }
+
+        if dmg.take_save_request() {
+            save(&mut dmg, args.save.as_deref())?;
+        }
+    }
+
+    save(&mut dmg, args.save.as_deref())?;
+
+    Ok(())
+}
+
+fn save(dmg: &mut Dmg, path: Option<&str>) -> anyhow::Result<()> {
+    if let (Some(path), Some(data)) = (path, dmg.save_data()) {
+        std::fs::write(path, data)?;
     }
 
     Ok(())